@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::f64;
+use std::hash::{Hash, Hasher};
+use std::string;
+
+use object_model::ValueRef;
+use values::{Tuple, String, Rope, Record, Integer, Float};
+
+// ================================================================================================
+
+/// How many levels of nested values `structural_hash` looks into. Hashing only the top of a value
+/// keeps it finite on cyclic records and still agrees with `structural_eq`.
+const HASH_DEPTH: usize = 4;
+
+/// Are `a` and `b` structurally equal?
+///
+/// Every value is equal to itself. Apart from that, numbers are equal if they have the same type
+/// and value (all NaNs being the same value, whether they are immediate or boxed), Strings and
+/// Ropes if they have the same characters, Tuples if they have the same length and equal elements
+/// and records if they have the same record type and equal fields. Everything else (including
+/// Symbols, which are interned) is only equal to itself. Comparing cyclic records terminates since
+/// pairs that are already being compared are assumed to be equal.
+pub fn structural_eq(a: ValueRef, b: ValueRef) -> bool {
+    Comparison { assumed: HashSet::new() }.eq(a, b)
+}
+
+/// Feed a hash of `value` to `state`. Structurally equal values (see `structural_eq`) have equal
+/// hashes.
+pub fn structural_hash<H: Hasher>(value: ValueRef, state: &mut H) {
+    hash(value, HASH_DEPTH, state)
+}
+
+struct Comparison {
+    /// The pairs of records that are being compared.
+    assumed: HashSet<(ValueRef, ValueRef)>
+}
+
+impl Comparison {
+    fn eq(&mut self, mut a: ValueRef, mut b: ValueRef) -> bool {
+        // Loop on the last components instead of recursing so that long lists do not exhaust the
+        // stack:
+        loop {
+            if a == b {
+                return true;
+            }
+            if let (Some(m), Some(n)) = (Integer::from_value(a), Integer::from_value(b)) {
+                return m == n;
+            }
+            if let (Some(x), Some(y)) = (Float::from_value(a), Float::from_value(b)) {
+                return x == y || x.is_nan() && y.is_nan();
+            }
+            if let Some(Some(equal)) = with_text(a, |s| with_text(b, |t| s == t)) {
+                return equal;
+            }
+            if a.typ().is_none() || a.typ() != b.typ() {
+                return false;
+            }
+
+            if let (Some(s), Some(t)) = (a.try_downcast::<Tuple>(), b.try_downcast::<Tuple>()) {
+                let (s, t) = (s.vals(), t.vals());
+                if s.len() != t.len() {
+                    return false;
+                }
+                if s.is_empty() {
+                    return true;
+                }
+                let last = s.len() - 1;
+                if !s[..last].iter().zip(&t[..last]).all(|(&x, &y)| self.eq(x, y)) {
+                    return false;
+                }
+                a = s[last];
+                b = t[last];
+                continue;
+            }
+
+            if let (Some(r), Some(s)) = (Record::from_value(a), Record::from_value(b)) {
+                if !self.assumed.insert((a, b)) {
+                    return true;
+                }
+                return r.fields().iter().zip(s.fields()).all(|pair| match pair {
+                    (&Some(x), &Some(y)) => self.eq(x, y),
+                    (&None, &None) => true,
+                    _ => false
+                });
+            }
+
+            return false;
+        }
+    }
+}
+
+fn hash<H: Hasher>(value: ValueRef, depth: usize, state: &mut H) {
+    if let Some(n) = Integer::from_value(value) {
+        n.hash(state);
+    } else if let Some(x) = Float::from_value(value) {
+        // 0.0 == -0.0 and NaNs only differ by their payloads:
+        (if x == 0.0 { 0.0 } else if x.is_nan() { f64::NAN } else { x }).to_bits().hash(state);
+    } else if with_text(value, |s| s.hash(state)).is_some() {
+    } else if let Some(t) = value.try_downcast::<Tuple>() {
+        t.vals().len().hash(state);
+        if depth > 0 {
+            for &v in t.vals() {
+                hash(v, depth - 1, state);
+            }
+        }
+    } else if let Some(r) = Record::from_value(value) {
+        r.typ().hash(state);
+        if depth > 0 {
+            for field in r.fields() {
+                if let Some(v) = *field {
+                    hash(v, depth - 1, state);
+                }
+            }
+        }
+    } else {
+        value.hash(state);
+    }
+}
+
+/// Call `f` with the characters of `value` if it is a String or a Rope.
+fn with_text<R, F: FnOnce(&str) -> R>(value: ValueRef, f: F) -> Option<R> {
+    if let Some(s) = value.try_downcast::<String>() {
+        Some(f(s.chars()))
+    } else if let Some(rope) = value.try_downcast::<Rope>() {
+        let mut buf = string::String::new();
+        rope.write_to(&mut buf);
+        Some(f(&buf))
+    } else {
+        None
+    }
+}
+
+// ================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::f64;
+    use std::hash::Hasher;
+
+    use object_model::{ValueRef, ValueRefT};
+    use super::{structural_eq, structural_hash};
+
+    fn int(n: isize) -> ValueRef { ValueRefT::from(n).into() }
+
+    fn float(x: f64) -> ValueRef { ValueRefT::<f64>::checked(x).unwrap().into() }
+
+    fn hash(value: ValueRef) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        structural_hash(value, &mut hasher);
+        hasher.finish()
+    }
+
+    // The heap value types are registered by the interpreter, which tests them.
+    #[test]
+    fn immediates() {
+        assert!(structural_eq(int(3), int(3)));
+        assert!(!structural_eq(int(3), int(4)));
+        assert!(!structural_eq(int(1), float(1.0)));
+        assert!(structural_eq(float(0.0), float(-0.0)));
+        assert_eq!(hash(float(0.0)), hash(float(-0.0)));
+        let nan = float(f64::NAN);
+        let other_nan = float(f64::from_bits(f64::NAN.to_bits() | 1 << 40));
+        assert!(nan != other_nan);
+        assert!(structural_eq(nan, other_nan));
+        assert_eq!(hash(nan), hash(other_nan));
+        assert_eq!(hash(int(42)), hash(int(42)));
+    }
+}
@@ -8,6 +8,7 @@ extern crate pcws_gc;
 
 pub mod object_model;
 pub mod values;
+pub mod equality;
 
 use core::nonzero::NonZero;
 use std::mem::{self, size_of, transmute};
@@ -16,11 +17,13 @@ use std::slice;
 use std::collections::HashMap;
 use std::any::TypeId;
 use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
 use std::fmt::{self, Debug, Display, Formatter};
 
-use pcws_gc::{GSize, Initializable, start_init, Generation};
-use object_model::{HeapValueSub, HeapValue, DynHeapValue, ValueRef, ValueRefT};
-use values::Type;
+use pcws_gc::{GSize, Initializable, start_init, Generation, Stats};
+use object_model::{HeapValueSub, Immediate, HeapValue, DynHeapValue, ValueRef, ValueRefT};
+use values::{Type, Record, Symbol, Tuple, Slice, String, Rope, Bytes, ByteBuffer, BigInt, Float,
+             Promise, MapNode, Map, Set};
 
 pub use values::SymbolTable;
 
@@ -51,9 +54,19 @@ pub struct Allocator {
 unsafe impl Sync for Allocator {}
 unsafe impl Send for Allocator {}
 
+/// The maximum heap size (in bytes) used unless `Allocator::set_max_heap` says otherwise.
+pub const DEFAULT_MAX_HEAP: usize = 4*1024*1024;
+
+/// The maximum heap size set by `Allocator::set_max_heap` (0 if not set).
+static MAX_HEAP: AtomicUsize = ATOMIC_USIZE_INIT;
+
 lazy_static! {
     static ref ALLOCATOR: Mutex<Allocator> = {
-        Mutex::new(Allocator::new(4*1024*1024)) // FIXME: Heap size should be set by client
+        let max_heap = match MAX_HEAP.load(Ordering::SeqCst) {
+            0 => DEFAULT_MAX_HEAP,
+            max_heap => max_heap
+        };
+        Mutex::new(Allocator::new(max_heap))
     };
 }
 
@@ -62,6 +75,11 @@ impl Allocator {
         ALLOCATOR.lock().unwrap()
     }
 
+    /// Set the maximum heap size in bytes. Has no effect once `instance()` has been called.
+    pub fn set_max_heap(max_heap: usize) {
+        MAX_HEAP.store(max_heap, Ordering::SeqCst);
+    }
+
     fn new(max_heap: usize) -> Allocator {
         Allocator {
             gc: Generation::new(max_heap),
@@ -73,6 +91,8 @@ impl Allocator {
         self.gc.mark_ref(vref)
     }
 
+    pub fn stats(&self) -> Stats { self.gc.stats() }
+
     pub unsafe fn collect_garbage(&mut self) {
         TypeRegistry::instance_mut().trace(self);
         self.symbols.mark_roots(&mut self.gc);
@@ -210,17 +230,55 @@ impl TypeRegistry {
         self.display_fns.insert(index, display_fn::<T>);
     }
 
+    /// Use `typ` as the dynamic type of the immediate type `T`.
+    fn register_immediate<T: Immediate + 'static>(&mut self, typ: ValueRefT<Type>) {
+        self.types.insert(TypeId::of::<T>(), typ);
+    }
+
     /// Get the dynamic type corresponding to the (previously registered) static type `T`.
     pub fn reify<T: HeapValueSub + 'static>(&self) -> ValueRefT<Type> {
         self.types[&TypeId::of::<T>()]
     }
 
+    /// The dynamic type of `value`, which for immediates is the one registered for their type by
+    /// `register_builtin_types`.
+    pub fn type_of(&self, value: ValueRef) -> ValueRefT<Type> {
+        value.typ().unwrap_or_else(|| {
+            let index = if value.is_immediate::<isize>() {
+                TypeId::of::<isize>()
+            } else if value.is_immediate::<f64>() {
+                TypeId::of::<f64>()
+            } else if value.is_immediate::<char>() {
+                TypeId::of::<char>()
+            } else {
+                TypeId::of::<bool>()
+            };
+            self.types[&index]
+        })
+    }
+
+    /// The registered type whose name is `name`, if any.
+    pub fn named(&self, name: &str) -> Option<ValueRefT<Type>> {
+        self.types.values().cloned()
+            .find(|typ| typ.name().map_or(false, |type_name| type_name.chars() == name))
+    }
+
     pub fn debug(&self, value: &HeapValue, f: &mut Formatter) -> Result<(), fmt::Error> {
-        unsafe { self.debug_fns[&self.type_indices[&value.typ]](value, f) }
+        unsafe { self.debug_fns[&self.type_index(value.typ)](value, f) }
     }
 
     pub fn display(&self, value: &HeapValue, f: &mut Formatter) -> Result<(), fmt::Error> {
-        unsafe { self.display_fns[&self.type_indices[&value.typ]](value, f) }
+        unsafe { self.display_fns[&self.type_index(value.typ)](value, f) }
+    }
+
+    /// The static type of the instances of `typ`. Record types are created at runtime, so they
+    /// are not registered themselves; their instances are `Record`:s.
+    fn type_index(&self, typ: ValueRefT<Type>) -> TypeId {
+        if typ.record_len().is_some() {
+            TypeId::of::<Record>()
+        } else {
+            self.type_indices[&typ]
+        }
     }
 
     fn trace(&mut self, heap: &mut Allocator) {
@@ -236,11 +294,58 @@ impl TypeRegistry {
     }
 }
 
-/// Register the static type `T`, creating the dynamic counterpart.
-pub fn register_static_t<T: HeapValueSub + Debug + Display + 'static>() {
+/// Register the static type `T`, creating the dynamic counterpart named `name`. Type names are
+/// `Symbol`:s, so this can only be called after `register_builtin_types`.
+pub fn register_static_t<T: HeapValueSub + Debug + Display + 'static>(name: &str) {
     lazy_static::initialize(&TYPES); // HACK
-    let typ = Type::from_static::<T>(&mut*Allocator::instance()).unwrap();
+    let heap = &mut *Allocator::instance();
+    let typ = Type::from_static::<T>(heap).unwrap();
     TypeRegistry::instance_mut().register_typ::<T>(typ);
+    name_type(heap, typ, name);
+}
+
+/// Register the types in `values` and those of the immediates. Immediate Ints and Floats have
+/// the same type as their boxed counterparts.
+pub fn register_builtin_types() {
+    // `Symbol` has to be registered before any type (including itself) can be named:
+    lazy_static::initialize(&TYPES); // HACK
+    {
+        let heap = &mut *Allocator::instance();
+        let typ = Type::from_static::<Symbol>(heap).unwrap();
+        TypeRegistry::instance_mut().register_typ::<Symbol>(typ);
+        name_type(heap, typ, "Symbol");
+        let type_type = TypeRegistry::instance().reify::<Type>();
+        name_type(heap, type_type, "Type");
+    }
+
+    register_static_t::<Tuple>("Tuple");
+    register_static_t::<Slice>("Slice");
+    register_static_t::<String>("String");
+    register_static_t::<Rope>("Rope");
+    register_static_t::<Bytes>("Bytes");
+    register_static_t::<ByteBuffer>("ByteBuffer");
+    register_static_t::<BigInt>("Int");
+    register_static_t::<Float>("Float");
+    register_static_t::<Promise>("Promise");
+    register_static_t::<Record>("Record");
+    register_static_t::<MapNode>("MapNode");
+    register_static_t::<Map>("Map");
+    register_static_t::<Set>("Set");
+
+    let heap = &mut *Allocator::instance();
+    let char_type = Symbol::new(heap, "Char").and_then(|name| Type::named(heap, name)).unwrap();
+    let bool_type = Symbol::new(heap, "Bool").and_then(|name| Type::named(heap, name)).unwrap();
+    let mut types = TypeRegistry::instance_mut();
+    let int_type = types.reify::<BigInt>();
+    let float_type = types.reify::<Float>();
+    types.register_immediate::<isize>(int_type);
+    types.register_immediate::<f64>(float_type);
+    types.register_immediate::<char>(char_type);
+    types.register_immediate::<bool>(bool_type);
+}
+
+fn name_type(heap: &mut Allocator, mut typ: ValueRefT<Type>, name: &str) {
+    typ.set_name(Symbol::new(heap, name).unwrap());
 }
 
 // ================================================================================================
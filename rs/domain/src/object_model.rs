@@ -30,6 +30,20 @@ pub trait Unbox {
     fn unbox(self) -> Self::Target;
 }
 
+/// A scalar type that is stored directly in a `ValueRef` (without a `HeapValue`).
+pub trait Immediate: Copy {
+    /// The `ValueRef` tag of the type.
+    const TAG: usize;
+}
+
+impl Immediate for isize { const TAG: usize = ValueRef::INT_TAG; }
+
+impl Immediate for f64 { const TAG: usize = ValueRef::FLOAT_TAG; }
+
+impl Immediate for char { const TAG: usize = ValueRef::CHAR_TAG; }
+
+impl Immediate for bool { const TAG: usize = ValueRef::BOOL_TAG; }
+
 // ================================================================================================
 
 /// A subtype of `HeapValue`.
@@ -248,7 +262,6 @@ impl ValueRef {
     const SHIFT: usize = 3;
     const TAG_MASK: usize = (1 << Self::SHIFT) - 1;
     const PTR_BIT: usize = 0b001;
-    const POINTY_BITS: usize = 0b011;
 
     const INT_TAG  : usize = 0b001;
     const FLOAT_TAG: usize = 0b011;
@@ -294,6 +307,23 @@ impl ValueRef {
         }
     }
 
+    /// The dynamic type of `self` if it is a `HeapValue`.
+    pub fn typ(self) -> Option<ValueRefT<Type>> {
+        self.ptr().map(|sptr| unsafe { sptr.as_ref() }.typ)
+    }
+
+    /// Is `self` an immediate `T`?
+    pub fn is_immediate<T: Immediate>(self) -> bool { self.0.get() & Self::TAG_MASK == T::TAG }
+
+    /// Get the immediate `T` in `self` if there is one.
+    pub fn try_unbox<T: Immediate>(self) -> Option<T> where ValueRefT<T>: Unbox<Target=T> {
+        if self.is_immediate::<T>() {
+            Some(unsafe { self.downcast::<T>() }.unbox())
+        } else {
+            None
+        }
+    }
+
     fn view(self) -> ValueView {
         let self_bits = self.0.get();
         unsafe {
@@ -329,7 +359,9 @@ impl ObjectRef for ValueRef {
         }
     }
 
-    fn is_pointy(self) -> bool { self.0.get() & Self::POINTY_BITS == Self::POINTY_BITS }
+    /// Every `HeapValue` has at least its `link` and `typ` to trace (blobs have no other refs, see
+    /// `HeapValue::ref_len`).
+    fn is_pointy(self) -> bool { self.is_ptr() }
 }
 
 impl Debug for ValueRef {
@@ -349,7 +381,7 @@ impl Display for ValueRef {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         match self.view() {
             ValueView::Int(n)   => Display::fmt(&n, f),
-            ValueView::Float(n) => Display::fmt(&n, f),
+            ValueView::Float(n) => Debug::fmt(&n, f), // Debug always has a '.' or exponent
             ValueView::Char(c)  => Display::fmt(&c, f),
             ValueView::Bool(b)  => Display::fmt(&b, f),
             ValueView::HeapValue(ptr) =>
@@ -392,6 +424,18 @@ impl From<isize> for ValueRefT<isize> {
     }
 }
 
+impl ValueRefT<f64> {
+    /// `n` as an immediate Float, or `None` if its low mantissa bits would be lost to the tag
+    /// (`From` would truncate them; use `values::Float::new` to box such floats).
+    pub fn checked(n: f64) -> Option<ValueRefT<f64>> {
+        if n.to_bits() as usize & ValueRef::TAG_MASK == 0 {
+            Some(n.into())
+        } else {
+            None
+        }
+    }
+}
+
 impl From<f64> for ValueRefT<f64> {
     fn from(n: f64) -> ValueRefT<f64> {
         ValueRefT(ValueRef(unsafe { NonZero::new_unchecked(
@@ -417,16 +461,32 @@ impl From<bool> for ValueRefT<bool> {
     }
 }
 
+impl ValueRefT<isize> {
+    /// The smallest immediate Int.
+    pub const MIN: isize = isize::min_value() >> ValueRef::SHIFT;
+    /// The largest immediate Int.
+    pub const MAX: isize = isize::max_value() >> ValueRef::SHIFT;
+
+    /// `n` as an immediate Int, or `None` if it is not in `MIN...MAX` (`From` would truncate it).
+    pub fn checked(n: isize) -> Option<ValueRefT<isize>> {
+        if Self::MIN <= n && n <= Self::MAX {
+            Some(n.into())
+        } else {
+            None
+        }
+    }
+}
+
 impl Unbox for ValueRefT<isize> {
     type Target = isize;
 
-    fn unbox(self) -> isize { ((self.0).0.get() >> ValueRef::SHIFT) as isize }
+    fn unbox(self) -> isize { ((self.0).0.get() as isize) >> ValueRef::SHIFT }
 }
 
 impl Unbox for ValueRefT<f64> {
     type Target = f64;
 
-    fn unbox(self) -> f64 { unsafe { transmute(((self.0).0.get() & !ValueRef::TAG_MASK) as f64) } }
+    fn unbox(self) -> f64 { f64::from_bits(((self.0).0.get() & !ValueRef::TAG_MASK) as u64) }
 }
 
 impl Unbox for ValueRefT<char> {
@@ -484,7 +544,7 @@ impl<T: HeapValueSub + Display> Display for ValueRefT<T> {
 mod tests {
     use std::mem::size_of;
 
-    use super::{ValueRef, ValueRefT};
+    use super::{Unbox, ValueRef, ValueRefT};
     use values::Type;
 
     #[test]
@@ -492,4 +552,13 @@ mod tests {
         assert_eq!(size_of::<Option<ValueRef>>(), size_of::<ValueRef>());
         assert_eq!(size_of::<Option<ValueRefT<Type>>>(), size_of::<ValueRefT<Type>>());
     }
+
+    #[test]
+    fn floats() {
+        for &n in &[0.0, -1.5, 1e10, ::std::f64::INFINITY] {
+            assert_eq!(ValueRefT::<f64>::checked(n).unwrap().unbox(), n);
+        }
+        assert!(ValueRefT::<f64>::checked(0.1).is_none());
+        assert_eq!(ValueRef::from(ValueRefT::from(-1.5f64)).try_unbox::<f64>(), Some(-1.5));
+    }
 }
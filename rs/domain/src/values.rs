@@ -1,37 +1,53 @@
 use std::mem::transmute;
+use std::iter;
 use std::str;
 use std::string;
 use std::fmt::{self, Debug, Display, Write, Formatter};
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::cmp::Ordering;
+use std::ops::{Add, Sub, Mul, Neg};
 
 use pcws_gc::{GSize, start_init, Generation};
 
 use super::{Allocator, TypeRegistry};
-use object_model::{HeapValueSub, RefTailed, BlobTailed, Sizing, HeapValue, ValueRef, ValueRefT};
+use equality::{structural_eq, structural_hash};
+use object_model::{HeapValueSub, RefTailed, BlobTailed, Sizing, HeapValue, ValueRef, ValueRefT,
+                   Unbox};
 
 // ================================================================================================
 
+/// The number of `ValueRef` fields (`ValueRef`, `ValueRefT<_>` or an `Option` of either) in a
+/// comma-terminated field list. The types are matched token by token, so the fields have to be
+/// passed as `tt`:s rather than `ty`:s, which would no longer match the literal type names.
 #[macro_export]
 macro_rules! count_vrefs {
     () => { 0 };
-    ( ValueRef $(, $Ts:ty)* ) => { 1 + count_vrefs!($($Ts),*) };
-    ( ValueRefT<$T:ty> $(, $Ts:ty)* ) => { 1 + count_vrefs!($($Ts),*) };
-    ( $T:ty $(, $Ts:ty)* ) => { count_vrefs!($($Ts),*) };
+    ( , ) => { 0 };
+    ( $name:ident : ValueRef , $($rest:tt)* ) => { 1 + count_vrefs!($($rest)*) };
+    ( $name:ident : ValueRefT<$T:ty> , $($rest:tt)* ) => { 1 + count_vrefs!($($rest)*) };
+    ( $name:ident : Option<ValueRef> , $($rest:tt)* ) => { 1 + count_vrefs!($($rest)*) };
+    ( $name:ident : Option<ValueRefT<$T:ty>> , $($rest:tt)* ) => { 1 + count_vrefs!($($rest)*) };
+    ( $name:ident : $T:ty , $($rest:tt)* ) => { count_vrefs!($($rest)*) };
 }
 
+/// The collector scans the first `MIN_REF_LEN` fields (and the tail of `RefTailed` values), so
+/// the `ValueRef` fields have to come first. `RefTailed` values can only have `ValueRef` fields;
+/// they can store scalars as immediates (e.g. `ValueRefT<isize>`).
 #[macro_export]
 macro_rules! heap_struct_base {
-    { pub struct $name:ident : $base:ty { $($field_names:ident : $field_types:ty),* }
+    { pub struct $name:ident : $base:ty { $($fields:tt)* }
       const SIZING: $crate::object_model::Sizing = $sizing:expr; } => {
         #[repr(C)]
         pub struct $name {
             base: $base,
-            $($field_names : $field_types),*
+            $($fields)*
         }
 
         impl $crate::object_model::HeapValueSub for $name {
             const SIZING: $crate::object_model::Sizing = $sizing;
-            const MIN_REF_LEN: usize = count_vrefs!($($field_types),*);
+            const MIN_REF_LEN: usize = count_vrefs!($($fields)* ,);
         }
     }
 }
@@ -39,14 +55,10 @@ macro_rules! heap_struct_base {
 #[macro_export]
 macro_rules! heap_struct {
     {
-        pub struct $name:ident : UniformHeapValue {
-            $($field_names:ident : $field_types:ty),*
-        }
+        pub struct $name:ident : UniformHeapValue { $($fields:tt)* }
     } => {
         heap_struct_base! {
-            pub struct $name : $crate::object_model::HeapValue {
-                $($field_names : $field_types),*
-            }
+            pub struct $name : $crate::object_model::HeapValue { $($fields)* }
 
             const SIZING: $crate::object_model::Sizing = $crate::object_model::Sizing::Static;
         }
@@ -55,14 +67,10 @@ macro_rules! heap_struct {
     };
 
     {
-        pub struct $name:ident : RefTailed<TailItem=$tail_typ:ty> {
-            $($field_names:ident : $field_types:ty),*
-        }
+        pub struct $name:ident : RefTailed<TailItem=$tail_typ:ty> { $($fields:tt)* }
     } => {
         heap_struct_base! {
-            pub struct $name : $crate::object_model::DynHeapValue {
-                $($field_names : $field_types),*
-            }
+            pub struct $name : $crate::object_model::DynHeapValue { $($fields)* }
 
             const SIZING: $crate::object_model::Sizing = $crate::object_model::Sizing::DynamicRefs;
         }
@@ -71,14 +79,10 @@ macro_rules! heap_struct {
     };
 
     {
-        pub struct $name:ident : BlobTailed<TailItem=$tail_typ:ty> {
-            $($field_names:ident : $field_types:ty),*
-        }
+        pub struct $name:ident : BlobTailed<TailItem=$tail_typ:ty> { $($fields:tt)* }
     } => {
         heap_struct_base! {
-            pub struct $name : $crate::object_model::DynHeapValue {
-                $($field_names : $field_types),*
-            }
+            pub struct $name : $crate::object_model::DynHeapValue { $($fields)* }
 
             const SIZING: $crate::object_model::Sizing = $crate::object_model::Sizing::DynamicBlob;
         }
@@ -136,9 +140,9 @@ impl Display for Tuple {
 
 heap_struct! {
     pub struct Slice: UniformHeapValue {
+        vals: ValueRefT<Tuple>,
         start: usize,
-        end: usize,
-        vals: ValueRefT<Tuple>
+        end: usize
     }
 }
 
@@ -146,7 +150,21 @@ impl Slice {
     pub fn new(heap: &mut Allocator, vals: ValueRefT<Tuple>, start: usize, end: usize)
         -> Option<ValueRefT<Slice>>
     {
-        heap.create_uniform(|base| Slice { base, start, end, vals })
+        heap.create_uniform(|base| Slice { base, vals, start, end })
+    }
+
+    pub fn len(&self) -> usize { self.end - self.start }
+
+    pub fn is_empty(&self) -> bool { self.start == self.end }
+
+    pub fn vals(&self) -> &[ValueRef] { &self.vals.vals()[self.start..self.end] }
+
+    /// The subslice `start..end` of `slice` (relative to the start of `slice`).
+    pub fn sub(heap: &mut Allocator, slice: ValueRefT<Slice>, start: usize, end: usize)
+        -> Option<ValueRefT<Slice>>
+    {
+        debug_assert!(start <= end && end <= slice.len());
+        Slice::new(heap, slice.vals, slice.start + start, slice.start + end)
     }
 
     pub fn uncons(heap: &mut Allocator, slice: ValueRefT<Slice>)
@@ -154,7 +172,7 @@ impl Slice {
     {
         if slice.start < slice.end {
             heap.create_uniform(|base| Slice {
-                base, start: slice.start + 1, end: slice.end, vals: slice.vals
+                base, vals: slice.vals, start: slice.start + 1, end: slice.end
             })
             .map(|remainder| Some((slice.vals.vals()[slice.start], remainder)))
         } else {
@@ -214,6 +232,179 @@ impl Display for String {
 
 // ================================================================================================
 
+/// Concatenation of strings that is only carried out when needed
+///
+/// The tail holds the pieces, which are `String`:s, Chars or other `Rope`:s. Building a string by
+/// repeatedly concatenating onto a rope copies the characters just once, when the rope is
+/// flattened (see `write_to`).
+heap_struct! {
+    pub struct Rope: RefTailed<TailItem=ValueRef> {}
+}
+
+impl Rope {
+    /// Create a rope of `pieces`, which must be `String`:s, Chars or `Rope`:s.
+    pub fn new(allocator: &mut Allocator, pieces: &[ValueRef]) -> Option<ValueRefT<Rope>> {
+        debug_assert!(pieces.iter().all(|&piece| Rope::is_piece(piece)));
+        allocator.create_with_slice(|base| Rope { base }, pieces)
+    }
+
+    /// Can `value` be a piece of a rope?
+    pub fn is_piece(value: ValueRef) -> bool {
+        value.is_instance::<String>() || value.is_immediate::<char>() || value.is_instance::<Rope>()
+    }
+
+    pub fn pieces(&self) -> &[ValueRef] { self.tail() }
+
+    /// Append the characters of `self` to `buf`.
+    pub fn write_to(&self, buf: &mut string::String) {
+        // Ropes that are built by appending one piece at a time are deep, so use an explicit
+        // stack instead of recursion:
+        let mut stack: Vec<ValueRef> = self.pieces().iter().rev().cloned().collect();
+        while let Some(piece) = stack.pop() {
+            if let Some(s) = piece.try_downcast::<String>() {
+                buf.push_str(s.chars());
+            } else if let Some(c) = piece.try_unbox::<char>() {
+                buf.push(c);
+            } else if let Some(rope) = piece.try_downcast::<Rope>() {
+                stack.extend(rope.pieces().iter().rev());
+            }
+        }
+    }
+}
+
+impl Debug for Rope {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Rope")
+         .field("base", &self.base)
+         .field("tail", &self.tail())
+         .finish()
+    }
+}
+
+impl Display for Rope {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        let mut chars = string::String::new();
+        self.write_to(&mut chars);
+        write!(f, "{:?}", chars) // like `String`
+    }
+}
+
+// ================================================================================================
+
+/// Fixed-length mutable byte array
+heap_struct! {
+    pub struct Bytes: BlobTailed<TailItem=u8> {}
+}
+
+impl Bytes {
+    pub fn new(allocator: &mut Allocator, bytes: &[u8]) -> Option<ValueRefT<Bytes>> {
+        allocator.create_with_slice(|base| Bytes { base }, bytes)
+    }
+
+    /// Create a `Bytes` of `len` zeroes.
+    pub fn zeroed(allocator: &mut Allocator, len: usize) -> Option<ValueRefT<Bytes>> {
+        allocator.create_with_iter(|base| Bytes { base }, len, iter::repeat(0u8))
+    }
+
+    pub fn bytes(&self) -> &[u8] { self.tail() }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] { self.tail_mut() }
+}
+
+impl Debug for Bytes {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Bytes")
+         .field("base", &self.base)
+         .field("bytes", &self.bytes())
+         .finish()
+    }
+}
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str("#<Bytes ")?;
+        for byte in self.bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_char('>')
+    }
+}
+
+// ================================================================================================
+
+/// Growable byte array
+///
+/// `storage` is at least as long as the contents. Every operation that can fail to allocate does so
+/// before it changes the buffer, so it can be retried after a collection.
+heap_struct! {
+    pub struct ByteBuffer: UniformHeapValue {
+        storage: ValueRefT<Bytes>,
+        len: ValueRefT<isize>
+    }
+}
+
+impl ByteBuffer {
+    const MIN_CAPACITY: usize = 16;
+
+    /// Create an empty buffer.
+    pub fn new(allocator: &mut Allocator) -> Option<ValueRefT<ByteBuffer>> {
+        let storage = Bytes::zeroed(allocator, ByteBuffer::MIN_CAPACITY)?;
+        allocator.create_uniform(|base| ByteBuffer { base, storage, len: ValueRefT::from(0isize) })
+    }
+
+    pub fn len(&self) -> usize { self.len.unbox() as usize }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// The contents.
+    pub fn bytes(&self) -> &[u8] { &self.storage.bytes()[..self.len()] }
+
+    /// Append `byte` to `buffer`.
+    pub fn push(allocator: &mut Allocator, buffer: ValueRefT<ByteBuffer>, byte: u8) -> Option<()> {
+        ByteBuffer::extend(allocator, buffer, &[byte])
+    }
+
+    /// Append `bytes` to `buffer`.
+    pub fn extend(allocator: &mut Allocator, mut buffer: ValueRefT<ByteBuffer>, bytes: &[u8])
+        -> Option<()>
+    {
+        let len = buffer.len();
+        let new_len = len + bytes.len();
+        let capacity = buffer.storage.bytes().len();
+        if new_len > capacity {
+            let mut storage = Bytes::zeroed(allocator, new_len.max(2 * capacity))?;
+            storage.bytes_mut()[..len].copy_from_slice(buffer.bytes());
+            buffer.storage = storage;
+        }
+        let mut storage = buffer.storage;
+        storage.bytes_mut()[len..new_len].copy_from_slice(bytes);
+        buffer.len = ValueRefT::from(new_len as isize);
+        Some(())
+    }
+}
+
+impl Debug for ByteBuffer {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("ByteBuffer")
+         .field("base", &self.base)
+         .field("storage", &self.storage)
+         .field("len", &self.len())
+         .finish()
+    }
+}
+
+impl Display for ByteBuffer {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str("#<ByteBuffer ")?;
+        for byte in self.bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_char('>')
+    }
+}
+
+// ================================================================================================
+
 /// Symbol (hash-consed string)
 heap_struct! {
     pub struct Symbol: BlobTailed<TailItem=u8> {}
@@ -232,6 +423,12 @@ impl Symbol {
                  })
     }
 
+    /// Create an uninterned symbol. It is distinct from every other symbol, even those with the
+    /// same characters.
+    pub fn fresh(allocator: &mut Allocator, chars: &str) -> Option<ValueRefT<Symbol>> {
+        allocator.create_with_slice(|base| Symbol { base }, chars.as_bytes())
+    }
+
     pub fn chars(&self) -> &str {
         unsafe { str::from_utf8_unchecked(self.tail()) }
     }
@@ -276,6 +473,363 @@ impl SymbolTable {
 
 // ================================================================================================
 
+/// Integer that does not fit in an immediate Int
+heap_struct! {
+    pub struct BigInt: BlobTailed<TailItem=u32> {
+        negative: bool
+    }
+}
+
+impl BigInt {
+    /// Create a `BigInt` from a sign and little-endian base-2^32 digits. Use `Integer::to_value`
+    /// instead unless the value is known not to fit in an immediate Int.
+    pub fn new(allocator: &mut Allocator, negative: bool, limbs: &[u32])
+        -> Option<ValueRefT<BigInt>>
+    {
+        allocator.create_with_slice(|base| BigInt { base, negative }, limbs)
+    }
+
+    pub fn is_negative(&self) -> bool { self.negative }
+
+    /// The little-endian base-2^32 digits of the magnitude.
+    pub fn limbs(&self) -> &[u32] { self.tail() }
+}
+
+impl Debug for BigInt {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("BigInt")
+         .field("base", &self.base)
+         .field("negative", &self.negative)
+         .field("limbs", &self.limbs())
+         .finish()
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        Display::fmt(&Integer { negative: self.negative, mag: self.limbs().to_vec() }, f)
+    }
+}
+
+/// An unboxed integer of any size for arithmetic on immediate Ints and `BigInt`s.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Integer {
+    negative: bool,
+    /// Little-endian base-2^32 digits without trailing zeroes (so zero has none)
+    mag: Vec<u32>
+}
+
+impl Integer {
+    /// The integer in the immediate Int or `BigInt` `value`.
+    pub fn from_value(value: ValueRef) -> Option<Integer> {
+        if let Some(n) = value.try_unbox::<isize>() {
+            Some(Integer::from(n))
+        } else if let Some(n) = value.try_downcast::<BigInt>() {
+            Some(Integer { negative: n.negative, mag: n.limbs().to_vec() })
+        } else {
+            None
+        }
+    }
+
+    /// Box `self` into an immediate Int if it fits or else a `BigInt`.
+    pub fn to_value(&self, allocator: &mut Allocator) -> Option<ValueRef> {
+        match self.to_isize().and_then(ValueRefT::<isize>::checked) {
+            Some(n) => Some(n.into()),
+            None => BigInt::new(allocator, self.negative, &self.mag).map(From::from)
+        }
+    }
+
+    /// Parse an optionally negative decimal numeral.
+    pub fn parse(numeral: &str) -> Option<Integer> {
+        let (negative, digits) = if numeral.starts_with('-') {
+            (true, &numeral[1..])
+        } else {
+            (false, numeral)
+        };
+        if digits.is_empty() {
+            return None;
+        }
+
+        let mut mag = Vec::new();
+        for c in digits.chars() {
+            mag_mul_add(&mut mag, 10, c.to_digit(10)?);
+        }
+        Some(Integer::new(negative, mag))
+    }
+
+    fn new(negative: bool, mut mag: Vec<u32>) -> Integer {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        Integer { negative: negative && !mag.is_empty(), mag }
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let n = self.mag.iter().rev().fold(0u64, |n, &limb| n << 32 | u64::from(limb));
+        if self.negative && n <= 1 << 63 {
+            Some((n as i64).wrapping_neg() as isize)
+        } else if !self.negative && n < 1 << 63 {
+            Some(n as isize)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_zero(&self) -> bool { self.mag.is_empty() }
+
+    /// The nearest `f64` (which may be infinite).
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap() // `str::parse` rounds correctly
+    }
+
+    /// The integer part of `n`, or `None` if `n` is infinite or NaN.
+    pub fn from_f64(n: f64) -> Option<Integer> {
+        if !n.is_finite() {
+            return None;
+        }
+        let n = n.trunc();
+        if n.abs() < 2f64.powi(63) {
+            return Some(Integer::from(n as isize));
+        }
+        // Such a large `n` is its 53-bit significand shifted left by at least 11 bits:
+        let bits = n.to_bits();
+        let shift = ((bits >> 52) & 0x7ff) as usize - 1075;
+        let significand = bits & ((1 << 52) - 1) | 1 << 52;
+        let mut mag = vec![0; shift / 32];
+        mag.push(significand as u32);
+        mag.push((significand >> 32) as u32);
+        mag_mul_add(&mut mag, 1 << (shift % 32), 0);
+        Some(Integer::new(n < 0.0, mag))
+    }
+
+    /// The truncating quotient and remainder, or `None` if `other` is zero.
+    pub fn div_rem(&self, other: &Integer) -> Option<(Integer, Integer)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quot, rem) = mag_div_rem(&self.mag, &other.mag);
+        Some((Integer::new(self.negative != other.negative, quot),
+              Integer::new(self.negative, rem)))
+    }
+}
+
+impl<'a> Add for &'a Integer {
+    type Output = Integer;
+
+    fn add(self, other: &'a Integer) -> Integer {
+        if self.negative == other.negative {
+            Integer::new(self.negative, mag_add(&self.mag, &other.mag))
+        } else if mag_cmp(&self.mag, &other.mag) != Ordering::Less {
+            Integer::new(self.negative, mag_sub(&self.mag, &other.mag))
+        } else {
+            Integer::new(other.negative, mag_sub(&other.mag, &self.mag))
+        }
+    }
+}
+
+impl<'a> Neg for &'a Integer {
+    type Output = Integer;
+
+    fn neg(self) -> Integer { Integer::new(!self.negative, self.mag.clone()) }
+}
+
+impl<'a> Sub for &'a Integer {
+    type Output = Integer;
+
+    fn sub(self, other: &'a Integer) -> Integer { self + &-other }
+}
+
+impl<'a> Mul for &'a Integer {
+    type Output = Integer;
+
+    fn mul(self, other: &'a Integer) -> Integer {
+        Integer::new(self.negative != other.negative, mag_mul(&self.mag, &other.mag))
+    }
+}
+
+impl From<isize> for Integer {
+    fn from(n: isize) -> Integer {
+        let abs = (n as i64).wrapping_abs() as u64;
+        Integer::new(n < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Integer) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Integer) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => mag_cmp(&self.mag, &other.mag),
+            (true, true) => mag_cmp(&other.mag, &self.mag),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less
+        }
+    }
+}
+
+impl Display for Integer {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        const CHUNK: u32 = 1_000_000_000;
+
+        if self.is_zero() {
+            return f.write_char('0');
+        }
+
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            chunks.push(mag_div_rem_small(&mut mag, CHUNK));
+        }
+
+        if self.negative {
+            f.write_char('-')?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn mag_cmp(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn mag_add(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut res = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in long.iter().enumerate() {
+        let sum = u64::from(x) + u64::from(*short.get(i).unwrap_or(&0)) + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+    res.push(carry as u32);
+    res
+}
+
+/// `a - b` where `a >= b`.
+fn mag_sub(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = i64::from(x) - i64::from(*b.get(i).unwrap_or(&0)) - borrow;
+        borrow = if diff < 0 { diff += 1 << 32; 1 } else { 0 };
+        res.push(diff as u32);
+    }
+    debug_assert_eq!(borrow, 0);
+    res
+}
+
+fn mag_mul(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let prod = u64::from(x) * u64::from(y) + u64::from(res[i + j]) + carry;
+            res[i + j] = prod as u32;
+            carry = prod >> 32;
+        }
+        res[i + b.len()] = carry as u32;
+    }
+    res
+}
+
+/// `mag = mag * m + a`
+fn mag_mul_add(mag: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = u64::from(a);
+    for limb in mag.iter_mut() {
+        let res = u64::from(*limb) * u64::from(m) + carry;
+        *limb = res as u32;
+        carry = res >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+/// Divide `mag` by `d` in place (trimming trailing zeroes) and return the remainder.
+fn mag_div_rem_small(mag: &mut Vec<u32>, d: u32) -> u32 {
+    let mut rem = 0u64;
+    for limb in mag.iter_mut().rev() {
+        let n = rem << 32 | u64::from(*limb);
+        *limb = (n / u64::from(d)) as u32;
+        rem = n % u64::from(d);
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    rem as u32
+}
+
+// OPTIMIZE: Use Knuth's algorithm D instead of shifting and subtracting one bit at a time.
+/// The quotient and remainder of `a` and the nonzero `b`.
+fn mag_div_rem(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut quot = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::with_capacity(b.len() + 1);
+    for i in (0..a.len() * 32).rev() {
+        mag_mul_add(&mut rem, 2, a[i / 32] >> (i % 32) & 1);
+        if mag_cmp(&rem, b) != Ordering::Less {
+            rem = mag_sub(&rem, b);
+            while rem.last() == Some(&0) {
+                rem.pop();
+            }
+            quot[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quot, rem)
+}
+
+// ================================================================================================
+
+/// Float that does not fit in an immediate (because its low mantissa bits are not all zero)
+heap_struct! {
+    pub struct Float: UniformHeapValue {
+        value: f64
+    }
+}
+
+impl Float {
+    /// `value` as an immediate Float if that is exact, else as a heap `Float`.
+    pub fn new(allocator: &mut Allocator, value: f64) -> Option<ValueRef> {
+        match ValueRefT::<f64>::checked(value) {
+            Some(n) => Some(n.into()),
+            None => allocator.create_uniform(|base| Float { base, value }).map(From::from)
+        }
+    }
+
+    /// The number in the immediate Float or `Float` `value`.
+    pub fn from_value(value: ValueRef) -> Option<f64> {
+        value.try_unbox::<f64>()
+             .or_else(|| value.try_downcast::<Float>().map(|n| n.value))
+    }
+
+    pub fn value(&self) -> f64 { self.value }
+}
+
+impl Debug for Float {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Float")
+         .field("base", &self.base)
+         .field("value", &self.value)
+         .finish()
+    }
+}
+
+impl Display for Float {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        Debug::fmt(&self.value, f) // like immediate Floats
+    }
+}
+
+// ================================================================================================
+
 /// Indirection
 heap_struct! {
     pub struct Promise: UniformHeapValue {}
@@ -316,7 +870,10 @@ impl Debug for Promise {
 
 impl Display for Promise {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        <_ as Display>::fmt(&self.base.link.unwrap(), f)
+        match self.base.link {
+            Some(value) => <_ as Display>::fmt(&value, f),
+            None => Display::fmt("#<promise>", f)
+        }
     }
 }
 
@@ -325,6 +882,8 @@ impl Display for Promise {
 /// A dynamic type.
 heap_struct! {
     pub struct Type: UniformHeapValue {
+        name: Option<ValueRefT<Symbol>>,
+        record_len: Option<usize>,
         gsize_with_dyn: usize,
         ref_len_with_dyn: usize
     }
@@ -334,6 +893,8 @@ impl Type {
     pub fn make(base: HeapValue, sizing: Sizing, min_gsize: GSize, min_ref_len: usize) -> Type {
         Type {
             base,
+            name: None,
+            record_len: None,
             gsize_with_dyn: usize::from(min_gsize) << 1 | match sizing {
                 Sizing::Static => 0,
                 Sizing::DynamicRefs | Sizing::DynamicBlob => 1
@@ -351,6 +912,34 @@ impl Type {
         )
     }
 
+    /// Create a new record type whose instances have `len` fields. Every call creates a distinct
+    /// type, even if `name` and `len` are the same.
+    pub fn record(allocator: &mut Allocator, name: ValueRefT<Symbol>, len: usize)
+        -> Option<ValueRefT<Type>>
+    {
+        allocator.create_uniform(|base| Type {
+            name: Some(name),
+            record_len: Some(len),
+            ..Type::make(base, Record::SIZING, GSize::of::<Record>(), Record::MIN_REF_LEN)
+        })
+    }
+
+    /// Create a type that has no instances on the heap, such as that of an immediate.
+    pub fn named(allocator: &mut Allocator, name: ValueRefT<Symbol>) -> Option<ValueRefT<Type>> {
+        allocator.create_uniform(|base| Type {
+            name: Some(name),
+            ..Type::make(base, Sizing::Static, GSize::from(0), 0)
+        })
+    }
+
+    pub fn name(&self) -> Option<ValueRefT<Symbol>> { self.name }
+
+    /// Set the name of a type that was created without one (see `register_static_t`).
+    pub(crate) fn set_name(&mut self, name: ValueRefT<Symbol>) { self.name = Some(name) }
+
+    /// The number of fields of instances if this is a record type.
+    pub fn record_len(&self) -> Option<usize> { self.record_len }
+
     /// The constant portion (or minimum) granule size of instances.
     pub fn uniform_gsize(&self) -> usize { self.gsize_with_dyn >> 1 }
 
@@ -368,6 +957,8 @@ impl Debug for Type {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("Type")
          .field("heap_value", &self.base)
+         .field("name", &self.name)
+         .field("record_len", &self.record_len)
          .field("gsize_with_dyn", &self.gsize_with_dyn)
          .field("ref_len_with_dyn", &self.ref_len_with_dyn)
          .finish()
@@ -376,6 +967,530 @@ impl Debug for Type {
 
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
-        write!(f, "Type({:x}, {:x})", self.gsize_with_dyn, self.ref_len_with_dyn)
+        match self.name {
+            Some(name) => f.write_str(name.chars()),
+            None => write!(f, "Type({:x}, {:x})", self.gsize_with_dyn, self.ref_len_with_dyn)
+        }
+    }
+}
+
+// ================================================================================================
+
+/// Instance of a record type (see `Type::record`)
+///
+/// The fields are created uninitialized and can each be initialized once. Instances have their
+/// record type as their `typ` instead of the (registered) type of `Record`, so `try_downcast`
+/// does not recognize them; use `Record::from_value` instead.
+heap_struct! {
+    pub struct Record: RefTailed<TailItem=Option<ValueRef>> {}
+}
+
+impl Record {
+    /// Create an instance of the record type `typ` with uninitialized fields.
+    pub fn new(allocator: &mut Allocator, typ: ValueRefT<Type>) -> Option<ValueRefT<Record>> {
+        let len = typ.record_len().expect("not a record type");
+        allocator.create_with_iter(|base| Record { base }, len, iter::repeat(None::<ValueRef>))
+                 .map(|mut record| {
+                     record.base.base.typ = typ;
+                     record
+                 })
+    }
+
+    /// `value` as a `Record` if it is an instance of a record type.
+    pub fn from_value(value: ValueRef) -> Option<ValueRefT<Record>> {
+        value.typ()
+             .and_then(|typ| typ.record_len())
+             .map(|_| unsafe { value.downcast() })
+    }
+
+    pub fn typ(&self) -> ValueRefT<Type> { self.base.base.typ }
+
+    pub fn fields(&self) -> &[Option<ValueRef>] { self.tail() }
+
+    /// Initialize the field at `index` to `value`.
+    pub fn init(&mut self, index: usize, value: ValueRef) -> Result<(), Reinit> {
+        let field = &mut self.tail_mut()[index];
+        if field.is_none() {
+            *field = Some(value);
+            Ok(())
+        } else {
+            Err(Reinit)
+        }
+    }
+}
+
+impl Debug for Record {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Record")
+         .field("base", &self.base)
+         .field("tail", &self.tail())
+         .finish()
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}(", self.typ())?;
+        for (i, field) in self.fields().iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match *field {
+                Some(value) => <_ as Display>::fmt(&value, f)?,
+                None => f.write_str("#<uninitialized>")?
+            }
+        }
+        f.write_char(')')
+    }
+}
+
+// ================================================================================================
+
+/// The number of hash bits that each level of a `MapNode` trie uses.
+const MAP_LEVEL_BITS: u32 = 5;
+
+/// The depth at which the hashes of keys (see `key_hash`) have been used up. The nodes at this
+/// depth are collision nodes.
+const MAP_MAX_DEPTH: u32 = 12;
+
+/// The hash that `MapNode`:s index keys by. Only the low `MAP_LEVEL_BITS * MAP_MAX_DEPTH` bits
+/// are used.
+fn key_hash(key: ValueRef) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    structural_hash(key, &mut hasher);
+    hasher.finish()
+}
+
+/// The position of `hash` in the bitmaps of a node at `depth`.
+fn map_bit(hash: u64, depth: u32) -> u32 {
+    1 << ((hash >> (depth * MAP_LEVEL_BITS)) as u32 & ((1 << MAP_LEVEL_BITS) - 1))
+}
+
+/// The index of the entry for `bit` among those in `bitmap`.
+fn map_index(bitmap: u32, bit: u32) -> usize { (bitmap & (bit - 1)).count_ones() as usize }
+
+/// Node of the hash array mapped trie that `Map`:s and `Set`:s are made of
+///
+/// The tail holds a key and a value for each bit set in `datamap` followed by a child node for
+/// each bit set in `nodemap`, both in bit order. Collision nodes (at `MAP_MAX_DEPTH`) have empty
+/// bitmaps and just key-value pairs in their tail. Apart from the root, every node contains at
+/// least two keys, so there is exactly one trie for each set of keys. Nodes are never mutated;
+/// updates copy the path from the root instead. The bitmaps are immediate Ints since the collector
+/// scans the fields of a `RefTailed` value along with its tail.
+heap_struct! {
+    pub struct MapNode: RefTailed<TailItem=ValueRef> {
+        datamap: ValueRefT<isize>,
+        nodemap: ValueRefT<isize>
+    }
+}
+
+impl MapNode {
+    fn new(allocator: &mut Allocator, datamap: u32, nodemap: u32, tail: &[ValueRef])
+        -> Option<ValueRefT<MapNode>>
+    {
+        let datamap = ValueRefT::from(datamap as isize);
+        let nodemap = ValueRefT::from(nodemap as isize);
+        allocator.create_with_slice(|base| MapNode { base, datamap, nodemap }, tail)
+    }
+
+    /// The bits of the keys stored directly in this node.
+    fn datamap(&self) -> u32 { self.datamap.unbox() as u32 }
+
+    /// The bits of the child nodes of this node.
+    fn nodemap(&self) -> u32 { self.nodemap.unbox() as u32 }
+
+    fn is_collision(&self) -> bool {
+        self.datamap() == 0 && self.nodemap() == 0 && !self.tail().is_empty()
+    }
+
+    /// The keys and values stored directly in this node, interleaved.
+    fn pairs(&self) -> &[ValueRef] {
+        let len = if self.is_collision() {
+            self.tail().len()
+        } else {
+            2 * self.datamap().count_ones() as usize
+        };
+        &self.tail()[..len]
+    }
+
+    fn children(&self) -> &[ValueRef] { &self.tail()[self.pairs().len()..] }
+
+    /// Call `f` with every key and value in the trie rooted at `self`.
+    fn each<F: FnMut(ValueRef, ValueRef)>(&self, f: &mut F) {
+        for pair in self.pairs().chunks(2) {
+            f(pair[0], pair[1]);
+        }
+        for &child in self.children() {
+            unsafe { child.downcast::<MapNode>() }.each(f);
+        }
+    }
+
+    fn get(mut node: ValueRefT<MapNode>, key: ValueRef) -> Option<ValueRef> {
+        let hash = key_hash(key);
+        let mut depth = 0;
+        loop {
+            if depth == MAP_MAX_DEPTH {
+                return node.pairs().chunks(2)
+                           .find(|pair| structural_eq(pair[0], key))
+                           .map(|pair| pair[1]);
+            }
+            let bit = map_bit(hash, depth);
+            if node.datamap() & bit != 0 {
+                let i = 2 * map_index(node.datamap(), bit);
+                let pairs = node.pairs();
+                return if structural_eq(pairs[i], key) { Some(pairs[i + 1]) } else { None };
+            } else if node.nodemap() & bit != 0 {
+                node = unsafe { node.children()[map_index(node.nodemap(), bit)].downcast() };
+                depth += 1;
+            } else {
+                return None;
+            }
+        }
+    }
+
+    /// `node` (at `depth`) with `key` mapped to `value` and whether `key` was not in `node`.
+    fn insert(allocator: &mut Allocator, node: ValueRefT<MapNode>, key: ValueRef, value: ValueRef,
+              hash: u64, depth: u32) -> Option<(ValueRefT<MapNode>, bool)>
+    {
+        let mut tail = node.tail().to_vec();
+
+        if depth == MAP_MAX_DEPTH {
+            let index = tail.chunks(2).position(|pair| structural_eq(pair[0], key));
+            let added = match index {
+                Some(i) => {
+                    tail[2*i + 1] = value;
+                    false
+                },
+                None => {
+                    tail.extend_from_slice(&[key, value]);
+                    true
+                }
+            };
+            return MapNode::new(allocator, 0, 0, &tail).map(|node| (node, added));
+        }
+
+        let (datamap, nodemap) = (node.datamap(), node.nodemap());
+        let bit = map_bit(hash, depth);
+        if datamap & bit != 0 {
+            let i = 2 * map_index(datamap, bit);
+            let (old_key, old_value) = (tail[i], tail[i + 1]);
+            if structural_eq(old_key, key) {
+                tail[i + 1] = value;
+                MapNode::new(allocator, datamap, nodemap, &tail).map(|node| (node, false))
+            } else {
+                // Push both pairs down into a new child:
+                let child = MapNode::pair(allocator, (old_key, old_value, key_hash(old_key)),
+                                          (key, value, hash), depth + 1)?;
+                tail.drain(i..i + 2);
+                let j = tail.len() - node.children().len() + map_index(nodemap, bit);
+                tail.insert(j, child.into());
+                MapNode::new(allocator, datamap ^ bit, nodemap | bit, &tail)
+                        .map(|node| (node, true))
+            }
+        } else if nodemap & bit != 0 {
+            let i = node.pairs().len() + map_index(nodemap, bit);
+            let child = unsafe { tail[i].downcast() };
+            let (child, added) = MapNode::insert(allocator, child, key, value, hash, depth + 1)?;
+            tail[i] = child.into();
+            MapNode::new(allocator, datamap, nodemap, &tail).map(|node| (node, added))
+        } else {
+            let i = 2 * map_index(datamap, bit);
+            tail.insert(i, value);
+            tail.insert(i, key);
+            MapNode::new(allocator, datamap | bit, nodemap, &tail).map(|node| (node, true))
+        }
+    }
+
+    /// A node at `depth` that contains the `(key, value, hash)`:s `a` and `b`.
+    fn pair(allocator: &mut Allocator, a: (ValueRef, ValueRef, u64), b: (ValueRef, ValueRef, u64),
+            depth: u32) -> Option<ValueRefT<MapNode>>
+    {
+        if depth == MAP_MAX_DEPTH {
+            return MapNode::new(allocator, 0, 0, &[a.0, a.1, b.0, b.1]);
+        }
+
+        let (a_bit, b_bit) = (map_bit(a.2, depth), map_bit(b.2, depth));
+        if a_bit == b_bit {
+            let child = MapNode::pair(allocator, a, b, depth + 1)?;
+            MapNode::new(allocator, 0, a_bit, &[child.into()])
+        } else if a_bit < b_bit {
+            MapNode::new(allocator, a_bit | b_bit, 0, &[a.0, a.1, b.0, b.1])
+        } else {
+            MapNode::new(allocator, a_bit | b_bit, 0, &[b.0, b.1, a.0, a.1])
+        }
+    }
+
+    /// `node` (at `depth`) without `key`, or `Some(None)` if `key` is not in `node`.
+    fn remove(allocator: &mut Allocator, node: ValueRefT<MapNode>, key: ValueRef, hash: u64,
+              depth: u32) -> Option<Option<ValueRefT<MapNode>>>
+    {
+        let mut tail = node.tail().to_vec();
+
+        if depth == MAP_MAX_DEPTH {
+            let index = tail.chunks(2).position(|pair| structural_eq(pair[0], key));
+            return match index {
+                Some(i) => {
+                    tail.drain(2*i..2*i + 2);
+                    MapNode::new(allocator, 0, 0, &tail).map(Some)
+                },
+                None => Some(None)
+            };
+        }
+
+        let (datamap, nodemap) = (node.datamap(), node.nodemap());
+        let bit = map_bit(hash, depth);
+        if datamap & bit != 0 {
+            let i = 2 * map_index(datamap, bit);
+            if !structural_eq(tail[i], key) {
+                return Some(None);
+            }
+            tail.drain(i..i + 2);
+            MapNode::new(allocator, datamap ^ bit, nodemap, &tail).map(Some)
+        } else if nodemap & bit != 0 {
+            let i = node.pairs().len() + map_index(nodemap, bit);
+            let child = unsafe { tail[i].downcast() };
+            let child = match MapNode::remove(allocator, child, key, hash, depth + 1)? {
+                Some(child) => child,
+                None => return Some(None)
+            };
+            if child.children().is_empty() && child.pairs().len() == 2 {
+                // Keep the trie canonical by pulling the last pair of `child` up into this node:
+                tail.remove(i);
+                let j = 2 * map_index(datamap, bit);
+                tail.insert(j, child.pairs()[1]);
+                tail.insert(j, child.pairs()[0]);
+                MapNode::new(allocator, datamap | bit, nodemap ^ bit, &tail).map(Some)
+            } else {
+                tail[i] = child.into();
+                MapNode::new(allocator, datamap, nodemap, &tail).map(Some)
+            }
+        } else {
+            Some(None)
+        }
+    }
+}
+
+impl Debug for MapNode {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("MapNode")
+         .field("base", &self.base)
+         .field("datamap", &self.datamap())
+         .field("nodemap", &self.nodemap())
+         .field("tail", &self.tail())
+         .finish()
+    }
+}
+
+impl Display for MapNode {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "#<MapNode {:x}, {:x}>", self.datamap(), self.nodemap())
+    }
+}
+
+// ================================================================================================
+
+/// Persistent hash map
+///
+/// Keys are compared with `structural_eq`, so mutating a record that is used as a key (by
+/// initializing its fields) can make it impossible to find.
+heap_struct! {
+    pub struct Map: UniformHeapValue {
+        root: ValueRefT<MapNode>,
+        len: usize
+    }
+}
+
+impl Map {
+    /// Create an empty map.
+    pub fn new(allocator: &mut Allocator) -> Option<ValueRefT<Map>> {
+        let root = MapNode::new(allocator, 0, 0, &[])?;
+        allocator.create_uniform(|base| Map { base, root, len: 0 })
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn get(&self, key: ValueRef) -> Option<ValueRef> { MapNode::get(self.root, key) }
+
+    /// `map` with `key` mapped to `value`.
+    pub fn insert(allocator: &mut Allocator, map: ValueRefT<Map>, key: ValueRef, value: ValueRef)
+        -> Option<ValueRefT<Map>>
+    {
+        let (root, added) = MapNode::insert(allocator, map.root, key, value, key_hash(key), 0)?;
+        let len = if added { map.len + 1 } else { map.len };
+        allocator.create_uniform(|base| Map { base, root, len })
+    }
+
+    /// `map` without `key` (which is just `map` if it does not contain `key`).
+    pub fn remove(allocator: &mut Allocator, map: ValueRefT<Map>, key: ValueRef)
+        -> Option<ValueRefT<Map>>
+    {
+        match MapNode::remove(allocator, map.root, key, key_hash(key), 0)? {
+            Some(root) => allocator.create_uniform(|base| Map { base, root, len: map.len - 1 }),
+            None => Some(map)
+        }
+    }
+
+    /// The keys and values in hash order.
+    pub fn entries(&self) -> Vec<(ValueRef, ValueRef)> {
+        let mut entries = Vec::with_capacity(self.len);
+        self.root.each(&mut |key, value| entries.push((key, value)));
+        entries
+    }
+}
+
+impl Debug for Map {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Map")
+         .field("base", &self.base)
+         .field("root", &self.root)
+         .field("len", &self.len)
+         .finish()
+    }
+}
+
+impl Display for Map {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        if self.is_empty() {
+            return f.write_str("{->}");
+        }
+        f.write_char('{')?;
+        for (i, (key, value)) in self.entries().into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} -> {}", key, value)?;
+        }
+        f.write_char('}')
+    }
+}
+
+// ================================================================================================
+
+/// Persistent hash set
+///
+/// This is a `Map` in disguise: the elements are the keys of a trie whose values are all `true`.
+heap_struct! {
+    pub struct Set: UniformHeapValue {
+        root: ValueRefT<MapNode>,
+        len: usize
+    }
+}
+
+impl Set {
+    /// Create an empty set.
+    pub fn new(allocator: &mut Allocator) -> Option<ValueRefT<Set>> {
+        let root = MapNode::new(allocator, 0, 0, &[])?;
+        allocator.create_uniform(|base| Set { base, root, len: 0 })
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    pub fn contains(&self, elem: ValueRef) -> bool { MapNode::get(self.root, elem).is_some() }
+
+    /// `set` with `elem` added.
+    pub fn insert(allocator: &mut Allocator, set: ValueRefT<Set>, elem: ValueRef)
+        -> Option<ValueRefT<Set>>
+    {
+        let present = ValueRefT::from(true).into();
+        let (root, added) = MapNode::insert(allocator, set.root, elem, present, key_hash(elem), 0)?;
+        let len = if added { set.len + 1 } else { set.len };
+        allocator.create_uniform(|base| Set { base, root, len })
+    }
+
+    /// `set` without `elem` (which is just `set` if it does not contain `elem`).
+    pub fn remove(allocator: &mut Allocator, set: ValueRefT<Set>, elem: ValueRef)
+        -> Option<ValueRefT<Set>>
+    {
+        match MapNode::remove(allocator, set.root, elem, key_hash(elem), 0)? {
+            Some(root) => allocator.create_uniform(|base| Set { base, root, len: set.len - 1 }),
+            None => Some(set)
+        }
+    }
+
+    /// The elements in hash order.
+    pub fn elems(&self) -> Vec<ValueRef> {
+        let mut elems = Vec::with_capacity(self.len);
+        self.root.each(&mut |elem, _| elems.push(elem));
+        elems
+    }
+}
+
+impl Debug for Set {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Set")
+         .field("base", &self.base)
+         .field("root", &self.root)
+         .field("len", &self.len)
+         .finish()
+    }
+}
+
+impl Display for Set {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str("Set(")?;
+        for (i, elem) in self.elems().into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            <_ as Display>::fmt(&elem, f)?;
+        }
+        f.write_char(')')
+    }
+}
+
+// ================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use object_model::HeapValueSub;
+    use super::{Integer, Tuple, Slice, ByteBuffer, BigInt, Type, Map};
+
+    fn int(numeral: &str) -> Integer { Integer::parse(numeral).unwrap() }
+
+    #[test]
+    fn ref_lens() {
+        assert_eq!(Tuple::MIN_REF_LEN, 0);
+        assert_eq!(BigInt::MIN_REF_LEN, 0);
+        assert_eq!(Slice::MIN_REF_LEN, 1);
+        assert_eq!(Type::MIN_REF_LEN, 1);
+        assert_eq!(Map::MIN_REF_LEN, 1);
+        assert_eq!(ByteBuffer::MIN_REF_LEN, 2);
+    }
+
+    #[test]
+    fn integer_display() {
+        for &numeral in &["0", "-1", "4294967296", "-123456789012345678901234567890"] {
+            assert_eq!(int(numeral).to_string(), numeral);
+        }
+        assert_eq!(int("-0").to_string(), "0");
+        assert_eq!(Integer::from(isize::min_value()).to_string(), "-9223372036854775808");
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        let big = int("340282366920938463463374607431768211456"); // 2^128
+        assert_eq!(&big + &int("-1"), int("340282366920938463463374607431768211455"));
+        assert_eq!(&int("1") - &big, int("-340282366920938463463374607431768211455"));
+        let square = int(concat!("1157920892373161954235709850086879078532699846656405",
+                                 "64039457584007913129639936"));
+        assert_eq!(&big * &big, square);
+        assert_eq!(big.div_rem(&int("-3")),
+                   Some((int("-113427455640312821154458202477256070485"), int("1"))));
+        assert_eq!(int("-7").div_rem(&int("2")), Some((int("-3"), int("-1"))));
+        assert_eq!(big.div_rem(&int("0")), None);
+        assert!(int("-5") < int("3") && int("18446744073709551616") > int("18446744073709551615"));
+    }
+
+    #[test]
+    fn integer_from_f64() {
+        assert_eq!(Integer::from_f64(-3.75), Some(int("-3")));
+        assert_eq!(Integer::from_f64(2f64.powi(63)), Some(int("9223372036854775808")));
+        assert_eq!(Integer::from_f64(-2f64.powi(63)), Some(int("-9223372036854775808")));
+        assert_eq!(Integer::from_f64(1e20), Some(int("100000000000000000000")));
+        assert_eq!(Integer::from_f64(::std::f64::NAN), None);
     }
 }
@@ -41,6 +41,9 @@ impl ArenaAllocator {
         }
     }
 
+    /// The number of arenas allocated so far.
+    pub fn allocated(&self) -> usize { self.allocated }
+
     // TODO: fn release(&mut self, ...)...
 }
 
@@ -25,6 +25,9 @@ impl BlockAllocator {
         }
     }
 
+    /// The number of arenas that blocks are allocated from.
+    pub fn arenas(&self) -> usize { self.arenas.allocated() }
+
     pub fn alloc_ms_block(&mut self) -> Option<(Unique<MSBlock>, Span<Uninitialized<usize>>)> {
         self.allocate(unsafe { NonZero::new_unchecked(1) })
             .and_then(|uptr|
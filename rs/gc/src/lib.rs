@@ -16,7 +16,7 @@ use std::ptr::NonNull;
 
 pub use layout::GSize;
 pub use util::{Uninitialized, Initializable, start_init};
-pub use mark_n_sweep::Generation;
+pub use mark_n_sweep::{Generation, Stats};
 
 /// Managed heap value.
 pub trait Object {
@@ -6,7 +6,7 @@ use intrusive_collections::{LinkedList, LinkedListLink, UnsafeRef, IntrusivePoin
 
 use super::{Object, ObjectRef};
 use util::{Uninitialized, Initializable, CeilDiv, Foam, Span, AllocSat};
-use layout::{Block, GSize};
+use layout::{Arena, Block, GSize};
 use block::BlockAllocator;
 use descriptor::{Descriptor, SubDescr, MSBlockAdapter, LargeObjRopeAdapter};
 
@@ -25,7 +25,22 @@ pub struct Generation<ORef: ObjectRef> {
     large_objs: LinkedList<LargeObjRopeAdapter>,
 
     current_mark: u8,
-    mark_stack: Vec<NonNull<ORef::Obj>>
+    mark_stack: Vec<NonNull<ORef::Obj>>,
+
+    collections: usize
+}
+
+/// Memory usage statistics of a `Generation`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    /// Bytes of memory obtained from the OS.
+    pub heap_bytes: usize,
+    /// The number of blocks holding small objects.
+    pub blocks: usize,
+    /// The number of large objects.
+    pub large_objects: usize,
+    /// The number of garbage collections so far.
+    pub collections: usize
 }
 
 impl<ORef, Obj> Generation<ORef> where ORef: ObjectRef<Obj=Obj>, Obj: Object<ORef=ORef> {
@@ -40,7 +55,9 @@ impl<ORef, Obj> Generation<ORef> where ORef: ObjectRef<Obj=Obj>, Obj: Object<ORe
             large_objs: LinkedList::new(LargeObjRopeAdapter::new()),
 
             current_mark: 1,
-            mark_stack: Vec::new()
+            mark_stack: Vec::new(),
+
+            collections: 0
         }
     }
 
@@ -96,6 +113,16 @@ impl<ORef, Obj> Generation<ORef> where ORef: ObjectRef<Obj=Obj>, Obj: Object<ORe
         self.sweep_all();
 
         self.current_mark.wrapping_add(2);
+        self.collections += 1;
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            heap_bytes: self.block_allocator.arenas() * Arena::SIZE,
+            blocks: self.active_blocks.iter().count(),
+            large_objects: self.large_objs.iter().count(),
+            collections: self.collections
+        }
     }
 
     fn release(&mut self, uptr: Initializable<usize>, gsize: NonZero<GSize>) {
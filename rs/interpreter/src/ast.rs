@@ -1,11 +1,13 @@
 use std::iter;
+use std::mem::transmute;
+use std::rc::Rc;
 use std::fmt::{self, Debug, Display, Formatter};
 use pretty::{self, DocAllocator, DocBuilder};
 
 use pcws_domain::Allocator;
-use pcws_domain::object_model::{RefTailed, ValueRef, ValueRefT};
+use pcws_domain::object_model::{RefTailed, Unbox, ValueRef, ValueRefT};
 use pcws_domain::values::{Symbol, Tuple};
-use pcws_syntax::cst::PrimOp;
+use pcws_syntax::cst::{PrimOp, Pos};
 
 // ================================================================================================
 
@@ -45,29 +47,80 @@ impl Pretty for ValueRef {
     }
 }
 
+/// The source position of an AST node. The file name is interned so that all the nodes of a file
+/// share it and the line and column are immediates.
+#[derive(Debug, Clone, Copy)]
+pub struct NodePos {
+    pub file: ValueRefT<Symbol>,
+    pub line: ValueRefT<isize>,
+    pub col: ValueRefT<isize>
+}
+
+/// The source position of the AST node `node`, if it has one.
+pub fn pos(node: ValueRef) -> Option<NodePos> {
+    typecase!(node, {
+        node: Function => Some(node.pos()),
+        node: Block => Some(node.pos()),
+        node: Match => Some(node.pos()),
+        node: Call => Some(node.pos()),
+        node: PrimCall => Some(node.pos()),
+        node: Lex => Some(node.pos()),
+        node: Dyn => Some(node.pos()),
+        node: Const => Some(node.pos()),
+        _ => None
+    })
+}
+
+/// Like `pos`, but converted back to a `Pos` (without the character index).
+pub fn source_pos(node: ValueRef) -> Option<Pos> {
+    pos(node).map(|pos| Pos {
+        file: Rc::new(pos.file.chars().to_string()),
+        index: 0,
+        line: pos.line.unbox() as usize,
+        col: pos.col.unbox() as usize
+    })
+}
+
 // ================================================================================================
 
 /// Function AST node
 heap_struct! {
     pub struct Function: RefTailed<TailItem=ValueRefT<Symbol>> {
-        body: ValueRef
+        body: ValueRef,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>,
+        name: ValueRefT<Symbol>
     }
 }
 
 impl Function {
-    pub fn new(allocator: &mut Allocator, params: &[ValueRefT<Symbol>], body: ValueRef)
-        -> Option<ValueRefT<Function>>
+    /// Create a function node. `name` is the variable that the function is bound to by its
+    /// definition, or empty if it is anonymous.
+    pub fn new(allocator: &mut Allocator, pos: NodePos, name: ValueRefT<Symbol>,
+               params: &[ValueRefT<Symbol>], body: ValueRef) -> Option<ValueRefT<Function>>
     {
-        allocator.create_with_slice(|base| Function { base, body }, params)
+        let NodePos { file, line, col } = pos;
+        allocator.create_with_slice(|base| Function { base, body, file, line, col, name },
+                                    params)
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
+    pub fn name(&self) -> ValueRefT<Symbol> { self.name }
+
     pub fn params(&self) -> &[ValueRefT<Symbol>] { self.tail() }
+
+    pub fn body(&self) -> ValueRef { self.body }
 }
 
 impl Debug for Function {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("Function")
          .field("base", &self.base)
+         .field("name", &self.name)
          .field("body", &self.body)
          .field("params", &self.params())
          .finish()
@@ -95,16 +148,26 @@ heap_struct! {
     pub struct Block: RefTailed<TailItem=ValueRef> {
         lex_binders: ValueRefT<Tuple>,
         dyn_binders: ValueRefT<Tuple>,
-        expr: ValueRef
+        expr: ValueRef,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Block {
-    pub fn new(allocator: &mut Allocator,
+    pub fn new(allocator: &mut Allocator, pos: NodePos,
                lex_binders: ValueRefT<Tuple>, dyn_binders: ValueRefT<Tuple>,
                stmts: &[ValueRef], expr: ValueRef) -> Option<ValueRefT<Block>>
     {
-        allocator.create_with_slice(|base| Block { base, lex_binders, dyn_binders, expr }, stmts)
+        let NodePos { file, line, col } = pos;
+        allocator.create_with_slice(|base| Block { base, lex_binders, dyn_binders, expr,
+                                                   file, line, col },
+                                    stmts)
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
     pub fn lex_binders(&self) -> ValueRefT<Tuple> { self.lex_binders }
@@ -147,18 +210,31 @@ impl Pretty for Block {
 heap_struct! {
     pub struct Match: RefTailed<TailItem=ValueRefT<Case>> {
         matchee: ValueRef,
-        default: ValueRef
+        default: ValueRef,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Match {
-    pub fn new(allocator: &mut Allocator, matchee: ValueRef, cases: &[ValueRefT<Case>],
-               default: ValueRef) -> Option<ValueRefT<Match>>
+    pub fn new(allocator: &mut Allocator, pos: NodePos, matchee: ValueRef,
+               cases: &[ValueRefT<Case>], default: ValueRef) -> Option<ValueRefT<Match>>
     {
-        allocator.create_with_slice(|base| Match { base, matchee, default }, cases)
+        let NodePos { file, line, col } = pos;
+        allocator.create_with_slice(|base| Match { base, matchee, default, file, line, col },
+                                    cases)
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
-    fn cases(&self) -> &[ValueRefT<Case>] { self.tail() }
+    pub fn matchee(&self) -> ValueRef { self.matchee }
+
+    pub fn cases(&self) -> &[ValueRefT<Case>] { self.tail() }
+
+    pub fn default(&self) -> ValueRef { self.default }
 }
 
 impl Debug for Match {
@@ -197,6 +273,8 @@ impl Pretty for Match {
 /// Case AST (match sub)node
 heap_struct! {
     pub struct Case: UniformHeapValue {
+        lex_defs: ValueRefT<Tuple>,
+        dyn_defs: ValueRefT<Tuple>,
         pattern: ValueRef,
         guard: ValueRef,
         body: ValueRef
@@ -204,11 +282,21 @@ heap_struct! {
 }
 
 impl Case {
-    pub fn new(allocator: &mut Allocator, pattern: ValueRef, guard: ValueRef, body: ValueRef)
-        -> Option<ValueRefT<Case>>
+    pub fn new(allocator: &mut Allocator, lex_defs: ValueRefT<Tuple>, dyn_defs: ValueRefT<Tuple>,
+               pattern: ValueRef, guard: ValueRef, body: ValueRef) -> Option<ValueRefT<Case>>
     {
-        allocator.create_uniform(|base| Case { base, pattern, guard, body })
+        allocator.create_uniform(|base| Case { base, lex_defs, dyn_defs, pattern, guard, body })
     }
+
+    pub fn lex_defs(&self) -> ValueRefT<Tuple> { self.lex_defs }
+
+    pub fn dyn_defs(&self) -> ValueRefT<Tuple> { self.dyn_defs }
+
+    pub fn pattern(&self) -> ValueRef { self.pattern }
+
+    pub fn guard(&self) -> ValueRef { self.guard }
+
+    pub fn body(&self) -> ValueRef { self.body }
 }
 
 impl Debug for Case {
@@ -239,15 +327,23 @@ impl Pretty for Case {
 /// Call AST node
 heap_struct! {
     pub struct Call: RefTailed<TailItem=ValueRef> {
-        callee: ValueRef
+        callee: ValueRef,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Call {
-    pub fn new(allocator: &mut Allocator, callee: ValueRef, args: &[ValueRef])
-        -> Option<ValueRefT<Call>>
+    pub fn new(allocator: &mut Allocator, pos: NodePos, callee: ValueRef,
+               args: &[ValueRef]) -> Option<ValueRefT<Call>>
     {
-        allocator.create_with_slice(|base| Call { base, callee }, args)
+        let NodePos { file, line, col } = pos;
+        allocator.create_with_slice(|base| Call { base, callee, file, line, col }, args)
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
     pub fn callee(&self) -> ValueRef { self.callee }
@@ -280,20 +376,31 @@ impl Pretty for Call {
 
 // ================================================================================================
 
-/// PrimCall AST node
+/// PrimCall AST node (`op` is stored as an immediate Int since it precedes the tail)
 heap_struct! {
     pub struct PrimCall: RefTailed<TailItem=ValueRef> {
-        op: PrimOp
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>,
+        op: ValueRefT<isize>
     }
 }
 
 impl PrimCall {
-    pub fn new(allocator: &mut Allocator, op: PrimOp, args: &[ValueRef])
+    pub fn new(allocator: &mut Allocator, pos: NodePos, op: PrimOp, args: &[ValueRef])
         -> Option<ValueRefT<PrimCall>>
     {
-        allocator.create_with_slice(|base| PrimCall { base, op }, args)
+        let op = ValueRefT::from(op as isize);
+        let NodePos { file, line, col } = pos;
+        allocator.create_with_slice(|base| PrimCall { base, file, line, col, op }, args)
     }
 
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
+    }
+
+    pub fn op(&self) -> PrimOp { unsafe { transmute(self.op.unbox() as usize) } }
+
     pub fn args(&self) -> &[ValueRef] { self.tail() }
 }
 
@@ -301,7 +408,7 @@ impl Debug for PrimCall {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("Call")
          .field("base", &self.base)
-         .field("op", &self.op)
+         .field("op", &self.op())
          .field("args", &self.args())
          .finish()
     }
@@ -312,7 +419,7 @@ display_pretty! { PrimCall }
 impl Pretty for PrimCall {
     fn pretty<'a, A: DocAllocator<'a>>(&'a self, docs: &'a A) -> DocBuilder<'a, A> {
         docs.text("(")
-            .append(docs.intersperse(iter::once(docs.as_string(self.op))
+            .append(docs.intersperse(iter::once(docs.as_string(self.op()))
                                           .chain(self.args().iter()
                                                      .map(|arg| arg.pretty(docs))),
                                      docs.text(" ")))
@@ -373,16 +480,29 @@ impl Pretty for Def {
 /// AST node for lexical variable names.
 heap_struct! {
     pub struct Lex: UniformHeapValue {
-        name: ValueRefT<Symbol>
+        name: ValueRefT<Symbol>,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Lex {
-    pub fn new(allocator: &mut Allocator, name: ValueRefT<Symbol>) -> Option<ValueRefT<Lex>> {
-        allocator.create_uniform(|base| Lex { base, name })
+    pub fn new(allocator: &mut Allocator, pos: NodePos, name: ValueRefT<Symbol>)
+        -> Option<ValueRefT<Lex>>
+    {
+        let NodePos { file, line, col } = pos;
+        allocator.create_uniform(|base| Lex { base, name, file, line, col })
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
     pub fn name(&self) -> ValueRefT<Symbol> { self.name }
+
+    /// Is this the pattern `_`, which matches anything without binding it?
+    pub fn is_wildcard(&self) -> bool { self.name.chars() == "_" }
 }
 
 impl Debug for Lex {
@@ -407,13 +527,23 @@ pretty_display! { Lex }
 /// AST node for dynamic variable names.
 heap_struct! {
     pub struct Dyn: UniformHeapValue {
-        name: ValueRefT<Symbol>
+        name: ValueRefT<Symbol>,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Dyn {
-    pub fn new(allocator: &mut Allocator, name: ValueRefT<Symbol>) -> Option<ValueRefT<Dyn>> {
-        allocator.create_uniform(|base| Dyn { base, name })
+    pub fn new(allocator: &mut Allocator, pos: NodePos, name: ValueRefT<Symbol>)
+        -> Option<ValueRefT<Dyn>>
+    {
+        let NodePos { file, line, col } = pos;
+        allocator.create_uniform(|base| Dyn { base, name, file, line, col })
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
     pub fn name(&self) -> ValueRefT<Symbol> { self.name }
@@ -441,13 +571,23 @@ pretty_display! { Dyn }
 /// AST node for constants.
 heap_struct! {
     pub struct Const: UniformHeapValue {
-        value: ValueRef
+        value: ValueRef,
+        file: ValueRefT<Symbol>,
+        line: ValueRefT<isize>,
+        col: ValueRefT<isize>
     }
 }
 
 impl Const {
-    pub fn new(allocator: &mut Allocator, value: ValueRef) -> Option<ValueRefT<Const>> {
-        allocator.create_uniform(|base| Const { base, value })
+    pub fn new(allocator: &mut Allocator, pos: NodePos, value: ValueRef)
+        -> Option<ValueRefT<Const>>
+    {
+        let NodePos { file, line, col } = pos;
+        allocator.create_uniform(|base| Const { base, value, file, line, col })
+    }
+
+    pub fn pos(&self) -> NodePos {
+        NodePos { file: self.file, line: self.line, col: self.col }
     }
 
     pub fn value(&self) -> ValueRef { self.value }
@@ -0,0 +1,135 @@
+use std::mem::transmute;
+use std::path::Path;
+
+use pcws_domain::Allocator;
+use pcws_domain::object_model::{ValueRef, ValueRefT};
+use pcws_domain::values::{self, Tuple, Symbol};
+use pcws_syntax::cst::{Expr, Pattern, Case, PrimOp, Const, Def, CstFactory, Pos};
+
+use env::Env;
+use inject::Inject;
+use interpret::{interpret, EvalError, RuntimeError};
+use module::{Modules, Exports};
+
+/// The modules that make up the prelude, in the order their exports are bound. The names exported
+/// by each of them are in scope in the ones after it and in user code, and shadow the same names
+/// from the modules before it (so `map` and `foldLeft` are those of `List`, not `HAMT`).
+pub const PRELUDE: &[&str] = &["__Bootstrap.ListTable", "__Bootstrap.TupleTable",
+                               "__Bootstrap.HAMT", "__Bootstrap.Types", "__Bootstrap.List",
+                               "core.core"];
+
+/// The directory that the modules built into the binary appear to be in.
+pub const EMBEDDED_DIR: &str = "<embedded>";
+
+/// The sources of the `PRELUDE` modules and the modules they require, by their paths in `lib`,
+/// when they are built into the binary.
+#[cfg(feature = "embedded-prelude")]
+const EMBEDDED_MODULES: &[(&str, &str)] = &[
+    ("__Bootstrap/HAMT.pcws", include_str!("../../../lib/__Bootstrap/HAMT.pcws")),
+    ("__Bootstrap/List.pcws", include_str!("../../../lib/__Bootstrap/List.pcws")),
+    ("__Bootstrap/ListTable.pcws", include_str!("../../../lib/__Bootstrap/ListTable.pcws")),
+    ("__Bootstrap/Tuple.pcws", include_str!("../../../lib/__Bootstrap/Tuple.pcws")),
+    ("__Bootstrap/TupleTable.pcws", include_str!("../../../lib/__Bootstrap/TupleTable.pcws")),
+    ("__Bootstrap/Types.pcws", include_str!("../../../lib/__Bootstrap/Types.pcws")),
+    ("core/core.pcws", include_str!("../../../lib/core/core.pcws"))
+];
+
+// ================================================================================================
+
+/// Wrap `program` in a block that defines the global `apply` and `unapply`.
+///
+/// The parser desugars every application `f a b` into `apply apply 0 (f, (a, b))`, so `apply` has
+/// to be in scope before any user code runs. Likewise the view pattern `(f a b)` matches the
+/// components returned by `unapply f value` against `a` and `b`. Both are ordinary piecewise
+/// functions and can thus be extended like any other. By default a view pattern just calls `f`
+/// on the value.
+pub fn with_apply(program: Expr) -> Expr {
+    let factory = CstFactory::new(Pos::default());
+    let apply = Def::new("apply");
+    let unapply = Def::new("unapply");
+
+    factory.block(vec![factory.def(factory.lex_def(&apply), forwarder(&factory, false)),
+                       factory.def(factory.lex_def(&unapply), forwarder(&factory, true))],
+                  program)
+}
+
+/// Create a function that calls its first argument with the rest of the arguments. If `wrap` is
+/// true, the rest of the arguments are wrapped in a tuple first.
+fn forwarder(factory: &CstFactory, wrap: bool) -> Expr {
+    let callee = Def::new("callee");
+    let args = Def::new("args");
+    let fargs = if wrap {
+        factory.primcall(PrimOp::Tuple, vec![factory.lex_use(&args)])
+    } else {
+        factory.lex_use(&args)
+    };
+
+    factory.function(vec![Case {
+        pattern: Pattern::PrimCall(Pos::default(), PrimOp::Tuple,
+                                   vec![factory.lex_def(&callee), factory.lex_def(&args)]),
+        guard: factory.constant(Const::Bool(true)),
+        body: factory.call(factory.lex_use(&callee),
+                           vec![factory.lex_use(&callee), factory.constant(Const::Int(0)), fargs])
+    }])
+}
+
+/// Create the root dynamic environment, which provides `$Std.Process.arguments` (a tuple of
+/// strings) from the host.
+pub fn host_denv(heap: &mut Allocator, arguments: &[String]) -> Option<ValueRefT<Env>> {
+    let args = arguments.iter()
+                        .map(|arg| values::String::new(heap, arg).map(ValueRef::from))
+                        .collect::<Option<Vec<_>>>()?;
+    let args = Tuple::new(heap, args.len(), args.into_iter())?;
+
+    let name = Symbol::new(heap, "Std.Process.arguments")?;
+    let denv = Env::block(heap, None, &[name])?;
+    denv.init(name, args.into()).ok()?;
+    Some(denv)
+}
+
+/// Evaluate the prelude in `denv` and return the lexical environment that binds its exports. The
+/// modules required after that are evaluated in that environment as well.
+///
+/// The prelude modules are read from the module search path of `modules`, unless the interpreter
+/// was built with the `embedded-prelude` feature. Like required modules they are only evaluated
+/// once, so e.g. the `Cons` of the prelude is the same as the one that `ListTable` requires.
+pub fn prelude(denv: ValueRefT<Env>, modules: &mut Modules)
+    -> Result<Option<ValueRefT<Env>>, RuntimeError>
+{
+    let mut lenv = None;
+    for &name in PRELUDE {
+        let file = modules.resolve(name, None)
+                          .ok_or_else(|| EvalError::NoModule(name.to_string()))?;
+        let value = match modules.get(&file) {
+            Some(value) => value,
+            None => {
+                let program = ::module::parse(&file)?;
+                let ast = program.inject(&mut *Allocator::instance()).ok_or(EvalError::OOM)?;
+                let value = interpret(ast.into(), lenv, denv, modules)?;
+                modules.insert(file, value);
+                value
+            }
+        };
+        if let Some(exports) = value.try_downcast::<Exports>() {
+            let names: &[ValueRefT<Symbol>] = unsafe { transmute(exports.names()) };
+            let env = Env::block(&mut *Allocator::instance(), lenv, names)
+                          .ok_or(EvalError::OOM)?;
+            for (&name, &value) in names.iter().zip(exports.values()) {
+                env.init(name, value).map_err(EvalError::from)?;
+            }
+            lenv = Some(env);
+            modules.set_prelude(lenv);
+        }
+    }
+    Ok(lenv)
+}
+
+/// The source of the module in `file` if it is built into the binary (and thus in `EMBEDDED_DIR`).
+#[cfg(feature = "embedded-prelude")]
+pub fn embedded_source(file: &Path) -> Option<&'static str> {
+    let relative = file.strip_prefix(EMBEDDED_DIR).ok()?;
+    EMBEDDED_MODULES.iter().find(|&&(path, _)| Path::new(path) == relative).map(|&(_, src)| src)
+}
+
+#[cfg(not(feature = "embedded-prelude"))]
+pub fn embedded_source(_: &Path) -> Option<&'static str> { None }
@@ -0,0 +1,50 @@
+use std::fmt::{self, Debug, Display, Formatter};
+
+use pcws_domain::Allocator;
+use pcws_domain::object_model::ValueRefT;
+
+use ast::Function;
+use env::Env;
+
+// ================================================================================================
+
+/// Function closure (a `Function` paired with the lexical environment it was created in)
+heap_struct! {
+    pub struct Closure: UniformHeapValue {
+        code: ValueRefT<Function>,
+        lenv: Option<ValueRefT<Env>>
+    }
+}
+
+impl Closure {
+    pub fn new(allocator: &mut Allocator, code: ValueRefT<Function>, lenv: Option<ValueRefT<Env>>)
+        -> Option<ValueRefT<Closure>>
+    {
+        allocator.create_uniform(|base| Closure { base, code, lenv })
+    }
+
+    pub fn code(&self) -> ValueRefT<Function> { self.code }
+
+    pub fn lenv(&self) -> Option<ValueRefT<Env>> { self.lenv }
+}
+
+impl Debug for Closure {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Closure")
+         .field("base", &self.base)
+         .field("code", &self.code)
+         .field("lenv", &self.lenv)
+         .finish()
+    }
+}
+
+impl Display for Closure {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        let name = self.code.name();
+        if name.chars().is_empty() {
+            f.write_str("#<fn>")
+        } else {
+            write!(f, "#<fn {}>", name.chars())
+        }
+    }
+}
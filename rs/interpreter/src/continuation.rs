@@ -0,0 +1,103 @@
+use std::fmt::{self, Debug, Display, Formatter};
+
+use pcws_domain::Allocator;
+use pcws_domain::object_model::{RefTailed, Unbox, ValueRef, ValueRefT};
+
+// ================================================================================================
+
+/// Delimited continuation (a copy of the stack segment above a prompt)
+///
+/// The saved frame pointers are relative to the start of the segment and the bottom frame has
+/// -1 as the frame pointer of its caller, so the segment can be reinstated anywhere on the stack.
+/// `top` is stored as an immediate Int since it precedes the tail.
+heap_struct! {
+    pub struct Continuation: RefTailed<TailItem=Option<ValueRef>> {
+        top: ValueRefT<isize>
+    }
+}
+
+impl Continuation {
+    /// Create a continuation from the stack segment `slots` whose top frame starts at `top`.
+    pub fn new(allocator: &mut Allocator, top: usize, slots: &[Option<ValueRef>])
+        -> Option<ValueRefT<Continuation>>
+    {
+        let top = ValueRefT::from(top as isize);
+        allocator.create_with_slice(|base| Continuation { base, top }, slots)
+    }
+
+    /// The index of the top frame in `slots()`. Meaningless if `slots()` is empty.
+    pub fn top(&self) -> usize { self.top.unbox() as usize }
+
+    pub fn slots(&self) -> &[Option<ValueRef>] { self.tail() }
+}
+
+impl Debug for Continuation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Continuation")
+         .field("base", &self.base)
+         .field("top", &self.top())
+         .field("slots", &self.slots())
+         .finish()
+    }
+}
+
+impl Display for Continuation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        Display::fmt("#<continuation>", f)
+    }
+}
+
+// ================================================================================================
+
+/// One-shot delimited continuation
+///
+/// Like a `Continuation`, but the slots are cleared when it is resumed so that it can only be
+/// resumed once and does not keep the captured frames alive afterwards. `top` is -1 once it has
+/// been resumed.
+heap_struct! {
+    pub struct OneShot: RefTailed<TailItem=Option<ValueRef>> {
+        top: ValueRefT<isize>
+    }
+}
+
+impl OneShot {
+    /// Create a one-shot continuation from the stack segment `slots` whose top frame starts at
+    /// `top`.
+    pub fn new(allocator: &mut Allocator, top: usize, slots: &[Option<ValueRef>])
+        -> Option<ValueRefT<OneShot>>
+    {
+        let top = ValueRefT::from(top as isize);
+        allocator.create_with_slice(|base| OneShot { base, top }, slots)
+    }
+
+    /// Append the captured slots to `stack` and return the index of the top frame among them, or
+    /// `None` if this continuation has already been resumed.
+    pub fn take(&mut self, stack: &mut Vec<Option<ValueRef>>) -> Option<usize> {
+        let top = self.top.unbox();
+        if top < 0 {
+            return None;
+        }
+        stack.extend_from_slice(self.tail());
+        for slot in self.tail_mut() {
+            *slot = None;
+        }
+        self.top = ValueRefT::from(-1isize);
+        Some(top as usize)
+    }
+}
+
+impl Debug for OneShot {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("OneShot")
+         .field("base", &self.base)
+         .field("top", &self.top.unbox())
+         .field("slots", &self.tail())
+         .finish()
+    }
+}
+
+impl Display for OneShot {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        Display::fmt("#<continuation>", f)
+    }
+}
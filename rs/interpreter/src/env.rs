@@ -5,28 +5,29 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
 use pcws_domain::Allocator;
-use pcws_domain::object_model::{RefTailed, ValueRef, ValueRefT};
-use pcws_domain::values::{Promise, Symbol, Reinit};
+use pcws_domain::object_model::{RefTailed, Unbox, ValueRef, ValueRefT};
+use pcws_domain::values::{Promise, Symbol};
 
 // ================================================================================================
 
 #[derive(Debug)]
 pub struct Unbound(ValueRefT<Symbol>);
 
+impl Unbound {
+    pub fn name(&self) -> ValueRefT<Symbol> { self.0 }
+}
+
 #[derive(Debug)]
 pub enum InitError {
     Unbound(Unbound),
-    Reinit(Reinit)
+    /// The variable was already initialized.
+    Reinit(ValueRefT<Symbol>)
 }
 
 impl From<Unbound> for InitError {
     fn from(err: Unbound) -> InitError { InitError::Unbound(err) }
 }
 
-impl From<Reinit> for InitError {
-    fn from(err: Reinit) -> InitError { InitError::Reinit(err) }
-}
-
 // ================================================================================================
 
 #[derive(Debug)]
@@ -43,6 +44,11 @@ heap_struct! {
 }
 
 impl Env {
+    /// An environment that binds nothing.
+    pub fn empty(heap: &mut Allocator, parent: Option<ValueRefT<Env>>) -> Option<ValueRefT<Env>> {
+        Env::block(heap, parent, &[])
+    }
+
     pub fn block(heap: &mut Allocator, parent: Option<ValueRefT<Env>>, names: &[ValueRefT<Symbol>])
         -> Option<ValueRefT<Env>>
     {
@@ -86,12 +92,42 @@ impl Env {
         }
     }
 
+    /// Initialize the variable `name` (which must have been declared in `self` or its ancestors).
+    pub fn init(&self, name: ValueRefT<Symbol>, value: ValueRef) -> Result<(), InitError> {
+        let mut promise: ValueRefT<Promise> = unsafe { self.get(name)?.unwrap().downcast() };
+        promise.init(value).map_err(|_| InitError::Reinit(name))
+    }
+
+    /// Bind `name` (which must have been declared in `self` or its ancestors) to `promise` instead
+    /// of the promise that it was declared with.
+    pub fn rebind(&mut self, name: ValueRefT<Symbol>, promise: ValueRefT<Promise>)
+        -> Result<(), Unbound>
+    {
+        match self.local_index(name) {
+            Some(i) => {
+                self.entries_mut()[i].value = Some(promise.into());
+                Ok(())
+            },
+            None => match self.parent {
+                Some(mut parent) => parent.rebind(name, promise),
+                None => Err(Unbound(name))
+            }
+        }
+    }
+
     fn get_local(&self, name: ValueRefT<Symbol>) -> Option<Option<ValueRef>> {
+        self.local_index(name).map(|i| self.entries()[i].value)
+    }
+
+    fn local_index(&self, name: ValueRefT<Symbol>) -> Option<usize> {
         let entries = self.entries();
+        if entries.is_empty() {
+            return None;
+        }
         let mut i = scaled_hash(name, entries.len());
         loop {
             match entries[i].key {
-                Some(k) if k == name => return Some(entries[i].value),
+                Some(k) if k == name => return Some(i),
                 Some(k) => { i = (i + 1) % entries.len(); },
                 None => return None
             }
@@ -137,29 +173,35 @@ fn scaled_hash<T: Hash>(v: T, len: usize) -> usize { hash(v) as usize % len }
 
 heap_struct! {
     pub struct EnvBuffer: RefTailed<TailItem=Option<ValueRef>> {
-        len: usize
+        len: ValueRefT<isize>
     }
 }
 
 impl EnvBuffer {
     pub fn with_capacity(heap: &mut Allocator, cap: usize) -> Option<ValueRefT<EnvBuffer>> {
-        heap.create_with_iter(|base| EnvBuffer { base, len: 0 },
+        heap.create_with_iter(|base| EnvBuffer { base, len: ValueRefT::from(0isize) },
                               cap, iter::repeat::<Option<ValueRef>>(None))
     }
 
+    pub fn copy(heap: &mut Allocator, buf: ValueRefT<EnvBuffer>) -> Option<ValueRefT<EnvBuffer>> {
+        heap.create_with_slice(|base| EnvBuffer { base, len: buf.len }, buf.tail())
+    }
+
     pub fn push(&mut self, val: ValueRef) {
-        let len = self.len; // HACK: Until NLL arrives.
+        let len = self.len.unbox() as usize; // HACK: Until NLL arrives.
         debug_assert!(len <= self.base.dyn_len);
         self.tail_mut()[len] = Some(val);
-        self.len += 1;
+        self.len = ValueRefT::from(len as isize + 1);
     }
+
+    pub fn vals(&self) -> &[Option<ValueRef>] { &self.tail()[..self.len.unbox() as usize] }
 }
 
 impl Debug for EnvBuffer {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("EnvBuffer")
          .field("base", &self.base)
-         .field("lenv", &self.len)
+         .field("len", &self.len.unbox())
          .field("tail", &self.tail())
          .finish()
     }
@@ -175,16 +217,14 @@ impl Display for EnvBuffer {
 
 #[cfg(test)]
 mod tests {
-    use pcws_domain::{Allocator, register_static_t};
+    use pcws_domain::Allocator;
     use pcws_domain::object_model::{ValueRefT, ValueRef};
     use pcws_domain::values::{Symbol, Promise};
     use super::Env;
 
     #[test]
     fn get() {
-        register_static_t::<Promise>();
-        register_static_t::<Symbol>();
-        register_static_t::<Env>();
+        ::register_types();
 
         let heap = &mut *Allocator::instance();
         let key = Symbol::new(heap, "foo").unwrap();
@@ -199,6 +239,23 @@ mod tests {
                       .force().unwrap(),
                    ValueRef::from(value));
     }
+
+    #[test]
+    fn rebind() {
+        ::register_types();
+
+        let heap = &mut *Allocator::instance();
+        let key = Symbol::new(heap, "foo").unwrap();
+        let parent = Env::block(heap, None, &[key]).unwrap();
+        let mut env = Env::empty(heap, Some(parent)).unwrap();
+        env.init(key, ValueRefT::from(5isize).into()).unwrap();
+
+        env.rebind(key, Promise::new(heap).unwrap()).unwrap();
+        assert_eq!(parent.get(key).unwrap().unwrap().force(), None);
+        env.init(key, ValueRefT::from(6isize).into()).unwrap();
+        assert_eq!(parent.get(key).unwrap().unwrap().force(),
+                   Some(ValueRef::from(ValueRefT::from(6isize))));
+    }
 }
 
 // ================================================================================================
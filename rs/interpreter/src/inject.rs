@@ -1,7 +1,7 @@
 use pcws_domain::Allocator;
 use pcws_domain::object_model::{ValueRef, ValueRefT};
-use pcws_domain::values::{String, Symbol, Tuple};
-use pcws_syntax::cst::{Expr, Stmt, Pattern, Case, Const};
+use pcws_domain::values::{String, Symbol, Tuple, Integer, Float};
+use pcws_syntax::cst::{Expr, Stmt, Pattern, Case, Const, PrimOp, Pos, DefRef};
 
 use ast;
 
@@ -25,74 +25,52 @@ impl Inject for Expr {
         use self::Expr::*;
 
         match self {
-            Function(_, params, body) =>
-                params.into_iter()
-                      .map(|param| Symbol::new(allocator, &param.borrow().name))
-                      .collect::<Option<Vec<_>>>()
-                      .and_then(|params|
-                          body.inject(allocator)
-                              .and_then(|body|
-                                  ast::Function::new(allocator, &params, body).map(From::from)
+            Function(pos, params, body) => inject_function(allocator, pos, "", params, *body),
+            Block(pos, stmts, expr) =>
+                inject_pos(allocator, &pos)
+                   .and_then(|pos|
+                       stmts.into_iter()
+                            .map(|stmt| inject_stmt(stmt, allocator))
+                            .collect::<Option<Vec<_>>>()
+                            .and_then(|stmts|
+                                expr.inject(allocator)
+                                    .and_then(|expr| {
+                                        let (lbs, dbs) = block_binders(&stmts);
+                                        Tuple::new(allocator, lbs.len(), lbs.into_iter())
+                                            .and_then(|lbs|
+                                                Tuple::new(allocator, dbs.len(), dbs.into_iter())
+                                                    .and_then(|dbs|
+                                                        ast::Block::new(allocator, pos, lbs, dbs,
+                                                                        &stmts, expr)
+                                                            .map(From::from)
+                                                    )
+                                            )
+                                    })
+                            )
+                   ),
+            Match(pos, matchee, cases, default) =>
+                inject_pos(allocator, &pos)
+                   .and_then(|pos|
+                       matchee.inject(allocator)
+                              .and_then(|matchee|
+                                  cases.into_iter()
+                                       .map(|case| case.inject(allocator))
+                                       .collect::<Option<Vec<_>>>()
+                                       .and_then(|cases|
+                                           default.inject(allocator)
+                                                  .and_then(|default|
+                                                      ast::Match::new(allocator, pos, matchee,
+                                                                      &cases, default)
+                                                          .map(From::from)
+                                                  )
+                                       )
                               )
-                      ),
-            Block(_, stmts, expr) =>
-                stmts.into_iter()
-                     .map(|stmt| inject_stmt(stmt, allocator))
-                     .collect::<Option<Vec<_>>>()
-                     .and_then(|stmts|
-                         expr.inject(allocator)
-                             .and_then(|expr| {
-                                 let (lbs, dbs) = block_binders(&stmts);
-                                 Tuple::new(allocator, lbs.len(), lbs.into_iter())
-                                     .and_then(|lbs|
-                                          Tuple::new(allocator, dbs.len(), dbs.into_iter())
-                                              .and_then(|dbs|
-                                                  ast::Block::new(allocator, lbs, dbs,
-                                                                  &stmts, expr).map(From::from)
-                                              )
-                                     )
-                             })
-                     ),
-            Match(_, matchee, cases, default) =>
-                matchee.inject(allocator)
-                       .and_then(|matchee|
-                           cases.into_iter()
-                                .map(|case| case.inject(allocator))
-                                .collect::<Option<Vec<_>>>()
-                                .and_then(|cases|
-                                    default.inject(allocator)
-                                           .and_then(|default|
-                                               ast::Match::new(allocator, matchee, &cases, default)
-                                                   .map(From::from)
-                                           )
-                                )
-                       ),
-            Call(_, callee, args) =>
-                callee.inject(allocator)
-                      .and_then(|callee|
-                          args.into_iter()
-                              .map(|arg| arg.inject(allocator))
-                              .collect::<Option<Vec<_>>>()
-                              .and_then(|args|
-                                  ast::Call::new(allocator, callee, &args).map(From::from)
-                              )
-                      ),
-            PrimCall(_, op, args) =>
-                args.into_iter()
-                    .map(|arg| arg.inject(allocator))
-                    .collect::<Option<Vec<_>>>()
-                    .and_then(|args|
-                        ast::PrimCall::new(allocator, op, &args).map(From::from)
-                    ),
-            Lex(_, def) =>
-                Symbol::new(allocator, &def.borrow().name)
-                       .and_then(|name| ast::Lex::new(allocator, name).map(From::from)),
-            Dyn(_, name) =>
-                Symbol::new(allocator, &name)
-                       .and_then(|name| ast::Dyn::new(allocator, name).map(From::from)),
-            Const(_, c) =>
-                c.inject(allocator)
-                 .and_then(|c| ast::Const::new(allocator, c).map(From::from))
+                   ),
+            Call(pos, callee, args) => inject_call(allocator, pos, *callee, args),
+            PrimCall(pos, op, args) => inject_primcall(allocator, pos, op, args),
+            Lex(pos, def) => inject_lex(allocator, pos, &def.borrow().name),
+            Dyn(pos, name) => inject_dyn(allocator, pos, &name),
+            Const(pos, c) => inject_const(allocator, pos, c)
         }
     }
 }
@@ -104,39 +82,101 @@ impl Inject for Pattern {
         use self::Pattern::*;
 
         match self {
-            Call(_, callee, args) =>
-                callee.inject(allocator)
-                      .and_then(|callee|
-                          args.into_iter()
-                              .map(|arg| arg.inject(allocator))
-                              .collect::<Option<Vec<_>>>()
-                              .and_then(|args|
-                                  ast::Call::new(allocator, callee, &args).map(From::from)
-                              )
-                      ),
-            PrimCall(_, op, args) =>
-                args.into_iter()
-                    .map(|arg| arg.inject(allocator))
-                    .collect::<Option<Vec<_>>>()
-                    .and_then(|args|
-                        ast::PrimCall::new(allocator, op, &args).map(From::from)
-                    ),
-            Lex(_, def) =>
-                Symbol::new(allocator, &def.borrow().name)
-                       .and_then(|name| ast::Lex::new(allocator, name).map(From::from)),
-            Dyn(_, name) =>
-                Symbol::new(allocator, &name)
-                       .and_then(|name| ast::Dyn::new(allocator, name).map(From::from)),
-            Const(_, c) =>
-                c.inject(allocator)
-                 .and_then(|c| ast::Const::new(allocator, c).map(From::from))
+            Call(pos, callee, args) => inject_call(allocator, pos, callee, args),
+            PrimCall(pos, op, args) => inject_primcall(allocator, pos, op, args),
+            Lex(pos, def) => inject_lex(allocator, pos, &def.borrow().name),
+            Dyn(pos, name) => inject_dyn(allocator, pos, &name),
+            Const(pos, c) => inject_const(allocator, pos, c)
         }
     }
 }
 
+fn inject_function(allocator: &mut Allocator, pos: Pos, name: &str, params: Vec<DefRef>,
+                   body: Expr) -> Option<ValueRef>
+{
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           Symbol::new(allocator, name)
+                  .and_then(|name|
+                      params.into_iter()
+                            .map(|param| Symbol::new(allocator, &param.borrow().name))
+                            .collect::<Option<Vec<_>>>()
+                            .and_then(|params|
+                                body.inject(allocator)
+                                    .and_then(|body|
+                                        ast::Function::new(allocator, pos, name, &params, body)
+                                            .map(From::from)
+                                    )
+                            )
+                  )
+       )
+}
+
+// The nodes that are shared by expressions and patterns:
+
+fn inject_call<C: Inject, A: Inject>(allocator: &mut Allocator, pos: Pos, callee: C, args: Vec<A>)
+    -> Option<ValueRef>
+{
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           callee.inject(allocator)
+                 .and_then(|callee|
+                     args.into_iter()
+                         .map(|arg| arg.inject(allocator).map(Into::into))
+                         .collect::<Option<Vec<_>>>()
+                         .and_then(|args|
+                             ast::Call::new(allocator, pos, callee.into(), &args).map(From::from)
+                         )
+                 )
+       )
+}
+
+fn inject_primcall<A: Inject>(allocator: &mut Allocator, pos: Pos, op: PrimOp, args: Vec<A>)
+    -> Option<ValueRef>
+{
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           args.into_iter()
+               .map(|arg| arg.inject(allocator).map(Into::into))
+               .collect::<Option<Vec<_>>>()
+               .and_then(|args|
+                   ast::PrimCall::new(allocator, pos, op, &args).map(From::from)
+               )
+       )
+}
+
+fn inject_lex(allocator: &mut Allocator, pos: Pos, name: &str) -> Option<ValueRef> {
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           Symbol::new(allocator, name)
+                  .and_then(|name| ast::Lex::new(allocator, pos, name).map(From::from))
+       )
+}
+
+fn inject_dyn(allocator: &mut Allocator, pos: Pos, name: &str) -> Option<ValueRef> {
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           Symbol::new(allocator, name)
+                  .and_then(|name| ast::Dyn::new(allocator, pos, name).map(From::from))
+       )
+}
+
+fn inject_const(allocator: &mut Allocator, pos: Pos, c: Const) -> Option<ValueRef> {
+    inject_pos(allocator, &pos)
+       .and_then(|pos|
+           c.inject(allocator)
+            .and_then(|c| ast::Const::new(allocator, pos, c).map(From::from))
+       )
+}
+
 fn inject_stmt(stmt: Stmt, allocator: &mut Allocator) -> Option<ValueRef> {
     match stmt {
-        Stmt::Def(pattern, expr) =>
+        Stmt::Def(pattern, expr) => {
+            // Name functions after the variable that they are bound to (for backtraces):
+            let name = match pattern {
+                Pattern::Lex(_, ref def) => def.borrow().name.clone(),
+                _ => ::std::string::String::new()
+            };
             pattern.inject(allocator)
                    .and_then(|pattern| {
                        let mut lbs = Vec::new();
@@ -146,14 +186,19 @@ fn inject_stmt(stmt: Stmt, allocator: &mut Allocator) -> Option<ValueRef> {
                        .and_then(|lbs|
                            Tuple::new(allocator, dbs.len(), dbs.into_iter())
                            .and_then(|dbs|
-                               expr.inject(allocator)
-                                   .and_then(|expr|
-                                       ast::Def::new(allocator, lbs, dbs, pattern, expr)
-                                                .map(From::from)
-                                   )
+                               match expr {
+                                   Expr::Function(pos, params, body) =>
+                                       inject_function(allocator, pos, &name, params, *body),
+                                   expr => expr.inject(allocator)
+                               }
+                               .and_then(|expr|
+                                   ast::Def::new(allocator, lbs, dbs, pattern, expr)
+                                            .map(From::from)
+                               )
                            )
                        )
-                   }),
+                   })
+        },
         Stmt::Expr(expr) => expr.inject(allocator)
     }
 }
@@ -165,25 +210,46 @@ impl Inject for Case {
         let Case { pattern, guard, body } = self;
 
         pattern.inject(allocator)
-               .and_then(|pattern|
-                   guard.inject(allocator)
-                        .and_then(|guard|
-                            body.inject(allocator)
-                                .and_then(|body|
-                                    ast::Case::new(allocator, pattern, guard, body).map(From::from)
+               .and_then(|pattern| {
+                   let mut lbs = Vec::new();
+                   let mut dbs = Vec::new();
+                   pattern_binders(pattern, &mut lbs, &mut dbs);
+                   Tuple::new(allocator, lbs.len(), lbs.into_iter())
+                   .and_then(|lbs|
+                       Tuple::new(allocator, dbs.len(), dbs.into_iter())
+                       .and_then(|dbs|
+                           guard.inject(allocator)
+                                .and_then(|guard|
+                                    body.inject(allocator)
+                                        .and_then(|body|
+                                            ast::Case::new(allocator, lbs, dbs,
+                                                           pattern, guard, body)
+                                        )
                                 )
-                        )
-               )
+                       )
+                   )
+               })
     }
 }
 
+/// The position of a node. Interning the file name makes the nodes of a file share it.
+fn inject_pos(allocator: &mut Allocator, pos: &Pos) -> Option<ast::NodePos> {
+    Symbol::new(allocator, &pos.file).map(|file| ast::NodePos {
+        file,
+        line: ValueRefT::from(pos.line as isize),
+        col: ValueRefT::from(pos.col as isize)
+    })
+}
+
 impl Inject for Const {
     type Target = ValueRef;
 
     fn inject(self, allocator: &mut Allocator) -> Option<ValueRef> {
         match self {
-            Const::Int(n) => Some(ValueRefT::from(n).into()),
-            Const::Float(n) => Some(ValueRefT::from(n).into()),
+            Const::Int(n) => Integer::from(n).to_value(allocator),
+            Const::BigInt(digits) =>
+                Integer::parse(&digits).expect("numeral").to_value(allocator),
+            Const::Float(n) => Float::new(allocator, n),
             Const::Char(c) => Some(ValueRefT::from(c).into()),
             Const::Bool(b) => Some(ValueRefT::from(b).into()),
             Const::String(cs) => String::new(allocator, &cs).map(From::from),
@@ -194,9 +260,17 @@ impl Inject for Const {
 
 fn pattern_binders(pat: ValueRef, lbs: &mut Vec<ValueRef>, dbs: &mut Vec<ValueRef>) {
     typecase!(pat, {
-        lvar: ast::Lex => lbs.push(lvar.name().into()),
+        lvar: ast::Lex => if !lvar.is_wildcard() {
+            lbs.push(lvar.name().into());
+        },
         dvar: ast::Dyn => dbs.push(dvar.name().into()),
-        _ => unimplemented!()
+        pats: ast::PrimCall => for &pat in pats.args() {
+            pattern_binders(pat, lbs, dbs);
+        },
+        view: ast::Call => for &pat in view.args() {
+            pattern_binders(pat, lbs, dbs);
+        },
+        _ => {} // constants bind nothing
     })
 }
 
@@ -1,13 +1,23 @@
 use std::mem::transmute;
 use std::slice;
 use std::iter;
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::path::{Path, PathBuf};
 
 use pcws_gc::GSize;
 use pcws_domain::Allocator;
+use pcws_domain::equality::structural_eq;
 use pcws_domain::object_model::{Unbox, ValueRef, ValueRefT};
-use pcws_domain::values::{Tuple, Slice};
-use ast::{Block, Def, Lex, Dyn, Const};
+use pcws_domain::values::{Tuple, Slice, Symbol, String, Promise};
+use pcws_syntax::cst::{PrimOp, Pos};
+use primops;
+use ast::{self, Function, Block, Match, Case, Call, PrimCall, Def, Lex, Dyn, Const};
 use env::{self, Env, EnvBuffer};
+use closure::Closure;
+use continuation::{Continuation, OneShot};
+use module::{self, Modules, Exports};
+use inject::Inject;
 
 // ================================================================================================
 
@@ -28,7 +38,7 @@ impl<T> AsRoot for Option<ValueRefT<T>> {
 }
 
 macro_rules! allocate {
-    ($f:path, ($($args:expr),*), { $itp:ident, $($live_in:ident),* } ) => {{
+    ($f:path, ($($args:expr),*), { $itp:ident $(, $live_in:ident)* } ) => {{
         let mut heap = Allocator::instance();
         if let Some(v) = $f(&mut*heap, $($args),*) {
             Ok(v)
@@ -45,23 +55,230 @@ macro_rules! allocate {
 
 // ================================================================================================
 
+/// The reason a pattern failed to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// There were too few or too many values for the pattern.
+    Argc,
+    /// The value was not of the type that the pattern destructures.
+    Type,
+    /// The value was not equal to the constant pattern.
+    Const,
+    /// `unapply` did not produce the components of the value.
+    View
+}
+
 #[derive(Debug)]
 pub enum EvalError {
     Unbound(env::Unbound),
-    Type,
+    /// A variable was read before it was initialized.
+    Uninitialized(ValueRefT<Symbol>),
+    /// A variable (or a `__redirect`ed promise if `None`) was initialized twice.
+    Reinit(Option<ValueRefT<Symbol>>),
+    /// The record field with this index was read before it was initialized.
+    UninitializedField(usize),
+    /// The record field with this index was initialized twice.
+    ReinitField(usize),
+    Type {
+        expected: &'static str,
+        received: ValueRef
+    },
+    Argc {
+        expected: usize,
+        received: usize
+    },
+    Bounds {
+        index: isize,
+        len: usize
+    },
+    /// The subsequence `start..end` is not within a sequence of length `len`.
+    Range {
+        start: isize,
+        end: isize,
+        len: usize
+    },
+    Mismatch(Mismatch),
+    IllegalPattern,
+    NoMethod,
+    Assertion,
+    Overflow,
+    DivByZero,
+    /// `__abort` was called without an enclosing `__prompt` with the same tag.
+    NoPrompt(ValueRef),
+    /// A one-shot continuation was resumed more than once.
+    ResumedTwice,
+    /// `__raise` was called without an enclosing `__try`.
+    Panic(ValueRef),
+    /// No file on the search path defines the required module.
+    NoModule(::std::string::String),
+    /// The module file could not be read or parsed.
+    BadModule(::std::string::String, ::std::string::String),
+    /// The modules (files) require each other, starting and ending with the same one.
+    CyclicRequire(Vec<::std::string::String>),
+    /// The export record does not have the requested member.
+    NotExported(ValueRefT<Symbol>),
+    /// The map does not contain the key.
+    NoKey(ValueRef),
+    /// The bytes are not valid UTF-8 from this offset on.
+    InvalidUtf8(usize),
+    /// The interpreter reached a state that its invariants should rule out.
+    Internal(&'static str),
     OOM
 }
 
+impl EvalError {
+    /// The name of the kind symbol of the exceptions that this error is turned into.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            EvalError::Unbound(_) => "unbound",
+            EvalError::Uninitialized(_) => "uninitialized",
+            EvalError::Reinit(_) => "reinit",
+            EvalError::UninitializedField(_) => "uninitialized",
+            EvalError::ReinitField(_) => "reinit",
+            EvalError::Type { .. } => "type",
+            EvalError::Argc { .. } => "argc",
+            EvalError::Bounds { .. } => "bounds",
+            EvalError::Range { .. } => "bounds",
+            EvalError::Mismatch(_) => "mismatch",
+            EvalError::IllegalPattern => "illegalPattern",
+            EvalError::NoMethod => "noMethod",
+            EvalError::Assertion => "assertion",
+            EvalError::Overflow => "overflow",
+            EvalError::DivByZero => "divByZero",
+            EvalError::NoPrompt(_) => "noPrompt",
+            EvalError::ResumedTwice => "resumedTwice",
+            EvalError::Panic(_) => "panic",
+            EvalError::NoModule(_) => "noModule",
+            EvalError::BadModule(..) => "badModule",
+            EvalError::CyclicRequire(_) => "cyclicRequire",
+            EvalError::NotExported(_) => "notExported",
+            EvalError::NoKey(_) => "noKey",
+            EvalError::InvalidUtf8(_) => "invalidUtf8",
+            EvalError::Internal(_) => "internal",
+            EvalError::OOM => "oom"
+        }
+    }
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            EvalError::Unbound(ref err) => write!(f, "unbound variable {}", err.name().chars()),
+            EvalError::Uninitialized(name) =>
+                write!(f, "variable {} read before initialization", name.chars()),
+            EvalError::Reinit(Some(name)) =>
+                write!(f, "variable {} initialized twice", name.chars()),
+            EvalError::Reinit(None) => write!(f, "promise initialized twice"),
+            EvalError::UninitializedField(index) =>
+                write!(f, "record field {} read before initialization", index),
+            EvalError::ReinitField(index) => write!(f, "record field {} initialized twice", index),
+            EvalError::Type { expected, received } =>
+                write!(f, "expected {}, got {}", expected, received),
+            EvalError::Argc { expected, received } =>
+                write!(f, "expected {} arguments, got {}", expected, received),
+            EvalError::Bounds { index, len } =>
+                write!(f, "index {} out of bounds for length {}", index, len),
+            EvalError::Range { start, end, len } =>
+                write!(f, "range {}..{} out of bounds for length {}", start, end, len),
+            EvalError::Mismatch(mismatch) => write!(f, "pattern mismatch ({})", match mismatch {
+                Mismatch::Argc => "wrong number of values",
+                Mismatch::Type => "wrong type",
+                Mismatch::Const => "unequal constant",
+                Mismatch::View => "`unapply` did not produce components"
+            }),
+            EvalError::IllegalPattern => write!(f, "illegal pattern"),
+            EvalError::NoMethod => write!(f, "no applicable method"),
+            EvalError::Assertion => write!(f, "assertion failed"),
+            EvalError::Overflow => write!(f, "arithmetic overflow"),
+            EvalError::DivByZero => write!(f, "division by zero"),
+            EvalError::NoPrompt(tag) => write!(f, "no prompt tagged {}", tag),
+            EvalError::ResumedTwice => write!(f, "one-shot continuation resumed twice"),
+            EvalError::Panic(value) => write!(f, "uncaught exception {}", value),
+            EvalError::NoModule(ref name) => write!(f, "module {} not found", name),
+            EvalError::BadModule(ref file, ref msg) =>
+                write!(f, "cannot load module {}: {}", file, msg),
+            EvalError::CyclicRequire(ref files) =>
+                write!(f, "cyclic module dependency: {}", files.join(" -> ")),
+            EvalError::NotExported(name) => write!(f, "{} is not exported", name.chars()),
+            EvalError::NoKey(key) => write!(f, "key {} not found", key),
+            EvalError::InvalidUtf8(offset) => write!(f, "invalid UTF-8 at byte {}", offset),
+            EvalError::Internal(msg) => write!(f, "internal error: {}", msg),
+            EvalError::OOM => write!(f, "out of memory")
+        }
+    }
+}
+
 impl From<env::Unbound> for EvalError {
     fn from(err: env::Unbound) -> EvalError { EvalError::Unbound(err) }
 }
 
+impl From<env::InitError> for EvalError {
+    fn from(err: env::InitError) -> EvalError {
+        match err {
+            env::InitError::Unbound(err) => EvalError::Unbound(err),
+            env::InitError::Reinit(name) => EvalError::Reinit(Some(name))
+        }
+    }
+}
+
 pub type EvalResult<T> = Result<T, EvalError>;
 
+/// An `EvalError` that escaped `interpret`, with the position of the AST node that caused it.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub error: EvalError,
+    pub pos: Option<Pos>,
+    /// The stack frames from the innermost outwards, starting with the function that failed.
+    pub backtrace: Vec<TraceFrame>
+}
+
+/// A stack frame in a `RuntimeError` backtrace.
+#[derive(Debug)]
+pub struct TraceFrame {
+    /// The name of the function that the frame belongs to.
+    pub name: ::std::string::String,
+    pub pos: Option<Pos>
+}
+
+impl From<EvalError> for RuntimeError {
+    fn from(error: EvalError) -> RuntimeError {
+        RuntimeError { error, pos: None, backtrace: Vec::new() }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "error: {}", self.error)?;
+        if let Some(ref pos) = self.pos {
+            write!(f, " at {}", pos)?;
+        }
+        for frame in self.backtrace.iter() {
+            write!(f, "\n    in {}", frame.name)?;
+            if let Some(ref pos) = frame.pos {
+                write!(f, " at {}", pos)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 // ================================================================================================
 
-pub fn interpret(program: ValueRef) -> EvalResult<ValueRef> {
-    Interpreter::new(/* OPTIMIZE: */ 1000, program).run()
+/// Evaluate `program` with the variables of `lenv` and the dynamic variables of `denv` in scope.
+/// The modules that it requires are looked up in and added to `modules`.
+pub fn interpret(program: ValueRef, lenv: Option<ValueRefT<Env>>, denv: ValueRefT<Env>,
+                 modules: &mut Modules) -> Result<ValueRef, RuntimeError>
+{
+    let exn_tag = Symbol::fresh(&mut *Allocator::instance(), "exception").ok_or(EvalError::OOM)?;
+    let mut interpreter = Interpreter::new(/* OPTIMIZE: */ 1000, program, lenv, denv, exn_tag,
+                                           mem::replace(modules, Modules::new(Vec::new())));
+    let res = interpreter.run();
+    *modules = mem::replace(&mut interpreter.modules, Modules::new(Vec::new()));
+    res.map_err(|error| RuntimeError {
+        pos: ast::source_pos(interpreter.control),
+        backtrace: interpreter.backtrace(),
+        error
+    })
 }
 
 // ================================================================================================
@@ -73,8 +290,15 @@ struct Interpreter {
     denv: Option<ValueRefT<Env>>,
     lenv_buf: Option<ValueRefT<EnvBuffer>>,
     denv_buf: Option<ValueRefT<EnvBuffer>>,
+    /// The function whose body is being evaluated (`None` at the top level).
+    code: Option<ValueRefT<Function>>,
     stack: Vec<Option<ValueRef>>,
-    fp: usize
+    fp: usize,
+    /// The frame pointer of the innermost `PromptFrame`, if any.
+    prompt: Option<usize>,
+    modules: Modules,
+    /// The tag of the prompts pushed by `__try`.
+    exn_tag: ValueRefT<Symbol>
 }
 
 #[derive(Debug)]
@@ -82,10 +306,14 @@ enum State {
     Eval,
     Exec,
     Parse(ValueRefT<Slice>),
+    Mismatch(Mismatch),
     Continue(ValueRef),
     Halt(ValueRef)
 }
 
+/// The fields of a stack frame, which follow its header of the caller's frame pointer, the saved
+/// registers `lenv`, `denv`, `lenv_buf`, `denv_buf` and `code` and the `TAG`. The first field is
+/// the AST node that the frame is working on, if any (see `Interpreter::backtrace`).
 trait SubFrame { const TAG: usize; }
 
 #[repr(C)]
@@ -101,35 +329,180 @@ struct DefFrame { def: ValueRefT<Def> }
 
 impl SubFrame for DefFrame { const TAG: usize = 0b1001; }
 
-#[derive(Clone, Copy)]
+/// Initializes the variables bound by a `Def` once its pattern has matched.
 #[repr(C)]
-struct CommitFrame;
+struct CommitFrame {
+    lex_defs: ValueRefT<Tuple>,
+    dyn_defs: ValueRefT<Tuple>
+}
 
 impl SubFrame for CommitFrame { const TAG: usize = 0b10001; }
 
+/// Evaluates the callee and arguments of a `Call`, leaving their values on the stack above the
+/// frame fields. `index` is the number of values evaluated so far.
+#[repr(C)]
+struct CallFrame {
+    call: ValueRefT<Call>,
+    index: ValueRefT<isize>
+}
+
+impl SubFrame for CallFrame { const TAG: usize = 0b11001; }
+
+#[repr(C)]
+struct MatcheeFrame { expr: ValueRefT<Match> }
+
+impl SubFrame for MatcheeFrame { const TAG: usize = 0b100001; }
+
+/// Dispatches a `Match` to its cases in order. Saves the environments that the default
+/// expression is evaluated in.
+#[repr(C)]
+struct MatchFrame {
+    expr: ValueRefT<Match>,
+    index: ValueRefT<isize>,
+    matchee: ValueRef
+}
+
+impl SubFrame for MatchFrame { const TAG: usize = 0b101001; }
+
+/// Initializes the variables bound by the pattern of `case` once it has matched and then proceeds
+/// to the guard.
+#[repr(C)]
+struct CaseFrame { case: ValueRefT<Case> }
+
+impl SubFrame for CaseFrame { const TAG: usize = 0b110001; }
+
+#[repr(C)]
+struct GuardFrame { case: ValueRefT<Case> }
+
+impl SubFrame for GuardFrame { const TAG: usize = 0b111001; }
+
+/// Matches the subpatterns of a tuple or view pattern in order. `outer` is the remainder of the
+/// enclosing sequence.
+#[repr(C)]
+struct PatsFrame {
+    pats: ValueRef,
+    index: ValueRefT<isize>,
+    outer: ValueRefT<Slice>
+}
+
+impl SubFrame for PatsFrame { const TAG: usize = 0b1000001; }
+
+/// Evaluates the callee of a view pattern.
+#[repr(C)]
+struct ViewFrame {
+    pats: ValueRefT<Call>,
+    value: ValueRef,
+    outer: ValueRefT<Slice>
+}
+
+impl SubFrame for ViewFrame { const TAG: usize = 0b1001001; }
+
+/// Waits for `unapply` to return the components of the value matched by a view pattern.
+#[repr(C)]
+struct UnapplyFrame {
+    pats: ValueRefT<Call>,
+    outer: ValueRefT<Slice>
+}
+
+impl SubFrame for UnapplyFrame { const TAG: usize = 0b1010001; }
+
+/// Evaluates the arguments of a `PrimCall`, leaving their values on the stack above the frame
+/// fields. `index` is the number of values evaluated so far.
+#[repr(C)]
+struct PrimCallFrame {
+    call: ValueRefT<PrimCall>,
+    index: ValueRefT<isize>
+}
+
+impl SubFrame for PrimCallFrame { const TAG: usize = 0b1011001; }
+
+/// Delimits the extent of a `__prompt`. `__abort` captures the frames above it into a
+/// `Continuation` (`__abortOnce` into a `OneShot`) and passes it to `handler`.
+///
+/// The prompt frames are linked together through `outer` (the frame pointer of the enclosing
+/// `PromptFrame` or -1) so that looking up a prompt by tag does not have to visit the other frames.
+#[repr(C)]
+struct PromptFrame {
+    tag: ValueRef,
+    handler: ValueRef,
+    outer: ValueRefT<isize>
+}
+
+impl SubFrame for PromptFrame { const TAG: usize = 0b1100001; }
+
+/// Caches the value of the module in `file` (a canonical path) once it has been evaluated.
+#[repr(C)]
+struct RequireFrame { file: ValueRefT<String> }
+
+impl SubFrame for RequireFrame { const TAG: usize = 0b1101001; }
+
+/// Calls `f` with the accumulator and each entry of a map or element of a set in turn for
+/// `__mapFold` and `__setFold`. `entries` holds the keys and values of the map alternately (with
+/// a `stride` of 2) or the elements of the set (with a `stride` of 1) and `index` is the number
+/// of entries folded so far.
+#[repr(C)]
+struct FoldFrame {
+    f: ValueRef,
+    entries: ValueRefT<Tuple>,
+    stride: ValueRefT<isize>,
+    index: ValueRefT<isize>
+}
+
+impl SubFrame for FoldFrame { const TAG: usize = 0b1110001; }
+
+/// Convert the (module) `program` to its heap representation.
+fn inject_program(heap: &mut Allocator, program: &::pcws_syntax::cst::Expr) -> Option<ValueRef> {
+    program.clone().inject(heap)
+}
+
+/// The value of the variable `name` from `value`, the result of looking it up in an `Env`.
+fn initialized(name: ValueRefT<Symbol>, value: Option<ValueRef>) -> EvalResult<ValueRef> {
+    value.and_then(ValueRef::force).ok_or(EvalError::Uninitialized(name))
+}
+
+/// The subpatterns of a tuple or view pattern.
+fn subpatterns<'a>(pats: ValueRef) -> &'a [ValueRef] {
+    typecase!(pats, {
+        pats: PrimCall => unsafe { transmute::<&[ValueRef], &'a [ValueRef]>(pats.args()) },
+        pats: Call => unsafe { transmute::<&[ValueRef], &'a [ValueRef]>(pats.args()) },
+        _ => unreachable!()
+    })
+}
+
 // FIXME: Environment save/restore
 impl Interpreter {
-    fn new(stack_capacity: usize, program: ValueRef) -> Interpreter {
+    fn new(stack_capacity: usize, program: ValueRef, lenv: Option<ValueRefT<Env>>,
+           denv: ValueRefT<Env>, exn_tag: ValueRefT<Symbol>, modules: Modules) -> Interpreter
+    {
         Interpreter {
             control: program,
-            lenv: None,
-            denv: None,
+            lenv,
+            denv: Some(denv),
             lenv_buf: None,
             denv_buf: None,
+            code: None,
             stack: Vec::with_capacity(stack_capacity),
-            fp: 0
+            fp: 0,
+            prompt: None,
+            modules,
+            exn_tag
         }
     }
 
     fn run(&mut self) -> EvalResult<ValueRef> {
         let mut state = State::Eval;
         loop { // trampoline
-            state = match state {
-                State::Eval => self.eval()?,
-                State::Exec => self.exec()?,
-                State::Parse(seq) => self.parse(seq)?,
-                State::Continue(value) => self.invoke(value)?,
+            let res = match state {
+                State::Eval => self.eval(),
+                State::Exec => self.exec(),
+                State::Parse(seq) => self.parse(seq),
+                State::Mismatch(mismatch) => self.mismatch(mismatch),
+                State::Continue(value) => self.invoke(value),
                 State::Halt(value) => return Ok(value)
+            };
+            state = match res {
+                Ok(state) => state,
+                Err(err) => self.raise_error(err)?
             }
         }
     }
@@ -137,6 +510,10 @@ impl Interpreter {
     fn eval(&mut self) -> EvalResult<State> {
         // println!("eval, fp = {}, sp = {}", self.fp, self.stack.len());
         typecase!(self.control, {
+            mut code: Function => {
+                let closure = allocate!(Closure::new, (code, self.lenv), {self, code})?;
+                Ok(State::Continue(closure.into()))
+            },
             mut block: Block =>
                 if 0 < block.stmts().len() {
                     let index = 0;
@@ -161,16 +538,35 @@ impl Interpreter {
                     self.control = block.expr();
                     Ok(State::Eval)
                 },
+            expr: Match => {
+                self.push_frame(MatcheeFrame { expr });
+                self.control = expr.matchee();
+                Ok(State::Eval)
+            },
+            call: Call => {
+                self.push_frame(CallFrame { call, index: ValueRefT::from(0isize) });
+                self.control = call.callee();
+                Ok(State::Eval)
+            },
+            call: PrimCall => {
+                self.push_frame(PrimCallFrame { call, index: ValueRefT::from(0isize) });
+                if call.args().is_empty() {
+                    self.primapply(call.op(), &[])
+                } else {
+                    self.control = call.args()[0];
+                    Ok(State::Eval)
+                }
+            },
             lvar: Lex => {
-                let val = self.lenv.unwrap().get(lvar.name())?.expect("uninitialized");
+                let val = initialized(lvar.name(), self.lenv.unwrap().get(lvar.name())?)?;
                 Ok(State::Continue(val))
             },
             dvar: Dyn => {
-                let val = self.denv.unwrap().get(dvar.name())?.expect("uninitialized");
+                let val = initialized(dvar.name(), self.lookup_dyn(dvar.name())?)?;
                 Ok(State::Continue(val))
             },
             c: Const => Ok(State::Continue(c.value())),
-            _ => unimplemented!()
+            _ => Err(EvalError::Internal("evaluated a value that is not an expression"))
         })
     }
 
@@ -199,16 +595,147 @@ impl Interpreter {
         })
     }
 
-    fn parse(&mut self, seq: ValueRefT<Slice>) -> EvalResult<State> {
+    fn parse(&mut self, mut seq: ValueRefT<Slice>) -> EvalResult<State> {
+        let (value, rest) = match allocate!(Slice::uncons, (seq), {self, seq})? {
+            Some(res) => res,
+            None => return Ok(State::Mismatch(Mismatch::Argc))
+        };
+
         typecase!(self.control, {
-            lex: Lex => {
-                // TODO
-                unimplemented!()
+            lvar: Lex => {
+                if !lvar.is_wildcard() {
+                    self.lenv_buf.unwrap().push(value);
+                }
+                Ok(State::Continue(rest.into()))
             },
-            _ => unimplemented!()
+            Dyn => {
+                self.denv_buf.unwrap().push(value);
+                Ok(State::Continue(rest.into()))
+            },
+            c: Const => Ok(if structural_eq(value, c.value()) {
+                State::Continue(rest.into())
+            } else {
+                State::Mismatch(Mismatch::Const)
+            }),
+            pats: PrimCall => match pats.op() {
+                PrimOp::Tuple => if let Some(tuple) = value.try_downcast::<Tuple>() {
+                    self.parse_subpatterns(pats.into(), tuple, rest)
+                } else if let Some(mut exports) = value.try_downcast::<Exports>() {
+                    // Modules are destructured by the values of their exports:
+                    let (mut pats, mut rest) = (pats, rest);
+                    let tuple = allocate!(Tuple::new, (exports.values().len(),
+                                                       exports.values().iter().cloned()),
+                                          {self, exports, pats, rest})?;
+                    self.parse_subpatterns(pats.into(), tuple, rest)
+                } else {
+                    Ok(State::Mismatch(Mismatch::Type))
+                },
+                _ => Err(EvalError::IllegalPattern)
+            },
+            view: Call => {
+                self.push_frame(ViewFrame { pats: view, value, outer: rest });
+                self.control = view.callee();
+                Ok(State::Eval)
+            },
+            _ => Err(EvalError::IllegalPattern)
         })
     }
 
+    /// Match the subpatterns of the compound pattern `pats` against the elements of `tuple` and
+    /// then continue with `outer`.
+    fn parse_subpatterns(&mut self, mut pats: ValueRef, mut tuple: ValueRefT<Tuple>,
+                         mut outer: ValueRefT<Slice>) -> EvalResult<State>
+    {
+        let subpats = subpatterns(pats);
+        if subpats.len() != tuple.vals().len() {
+            return Ok(State::Mismatch(Mismatch::Argc));
+        }
+        if subpats.is_empty() {
+            return Ok(State::Continue(outer.into()));
+        }
+
+        let inner = allocate!(Slice::new, (tuple, 0, tuple.vals().len()),
+                              {self, tuple, outer, pats})?;
+        self.push_frame(PatsFrame { pats, index: ValueRefT::from(0isize), outer });
+        self.control = subpatterns(pats)[0];
+        Ok(State::Parse(inner))
+    }
+
+    /// Unwind to the innermost `Match` that is trying a case and try the next one.
+    fn mismatch(&mut self, mismatch: Mismatch) -> EvalResult<State> {
+        while !self.stack.is_empty() {
+            match self.top_frame_tag() {
+                CaseFrame::TAG => {
+                    self.pop_frame();
+                    return self.next_case();
+                },
+                CommitFrame::TAG => break,
+                _ => self.pop_frame()
+            }
+        }
+        Err(EvalError::Mismatch(mismatch))
+    }
+
+    /// Try the case at the current index of the `MatchFrame` on top of the stack, falling through
+    /// to the default expression when the cases have been exhausted.
+    fn try_case(&mut self) -> EvalResult<State> {
+        let &MatchFrame { expr, index, matchee } = self.top_frame();
+        let index = index.unbox() as usize;
+
+        if index < expr.cases().len() {
+            let mut case = expr.cases()[index];
+
+            let mut lexen = case.lex_defs();
+            if lexen.vals().len() > 0 {
+                self.lenv = Some(allocate!(Env::block, (self.lenv,
+                                                       unsafe { transmute(lexen.vals()) }),
+                                           {self, lexen, case})?);
+            }
+
+            let mut dyns = case.dyn_defs();
+            if dyns.vals().len() > 0 {
+                self.denv = Some(allocate!(Env::block, (self.denv,
+                                                       unsafe { transmute(dyns.vals()) }),
+                                           {self, dyns, case})?);
+            }
+
+            self.lenv_buf = Some(allocate!(EnvBuffer::with_capacity,
+                                           (case.lex_defs().vals().len()), {self, case})?);
+            self.denv_buf = Some(allocate!(EnvBuffer::with_capacity,
+                                           (case.dyn_defs().vals().len()), {self, case})?);
+            self.push_frame(CaseFrame { case });
+            self.control = case.pattern();
+            let mut tuple = allocate!(Tuple::new, (1, iter::once(matchee)), {self, case})?;
+            let seq = allocate!(Slice::new, (tuple, 0, 1), {self, tuple})?;
+            Ok(State::Parse(seq))
+        } else {
+            self.pop_frame();
+            self.control = expr.default();
+            Ok(State::Eval)
+        }
+    }
+
+    /// Continue with the case after the current one in the `MatchFrame` on top of the stack.
+    fn next_case(&mut self) -> EvalResult<State> {
+        self.restore_envs();
+        let index = self.top_frame::<MatchFrame>().index.unbox();
+        self.top_frame_mut::<MatchFrame>().index = (index + 1).into();
+        self.try_case()
+    }
+
+    /// Initialize the variables named by `lex_defs` and `dyn_defs` from the environment buffers.
+    fn commit(&mut self, lex_defs: ValueRefT<Tuple>, dyn_defs: ValueRefT<Tuple>)
+        -> EvalResult<()>
+    {
+        for (&name, &value) in lex_defs.vals().iter().zip(self.lenv_buf.unwrap().vals()) {
+            self.lenv.unwrap().init(unsafe { name.downcast() }, value.unwrap())?;
+        }
+        for (&name, &value) in dyn_defs.vals().iter().zip(self.denv_buf.unwrap().vals()) {
+            self.denv.unwrap().init(unsafe { name.downcast() }, value.unwrap())?;
+        }
+        Ok(())
+    }
+
     fn invoke(&mut self, mut value: ValueRef) -> EvalResult<State> {
         // println!("invoke, fp = {}, sp = {}", self.fp, self.stack.len());
         if !self.stack.is_empty() {
@@ -229,18 +756,151 @@ impl Interpreter {
                 },
                 DefFrame::TAG => {
                     let &DefFrame { mut def } = self.top_frame();
+                    self.pop_frame();
                     self.lenv_buf =
                         Some(allocate!(EnvBuffer::with_capacity, (def.lex_defs().vals().len()),
                                        {self, value, def})?);
                     self.denv_buf =
                         Some(allocate!(EnvBuffer::with_capacity, (def.dyn_defs().vals().len()),
                                        {self, value, def})?);
-                    self.push_frame(CommitFrame);
+                    self.push_frame(CommitFrame { lex_defs: def.lex_defs(),
+                                                  dyn_defs: def.dyn_defs() });
                     self.control = def.pattern();
                     let mut tuple = allocate!(Tuple::new, (1, iter::once(value)), {self, value})?;
                     let seq = allocate!(Slice::new, (tuple, 0, 1), {self, tuple})?;
                     Ok(State::Parse(seq))
                 },
+                CommitFrame::TAG => {
+                    let &CommitFrame { lex_defs, dyn_defs } = self.top_frame();
+                    self.commit(lex_defs, dyn_defs)?;
+                    self.pop_frame();
+                    Ok(State::Continue(value))
+                },
+                MatcheeFrame::TAG => {
+                    let &MatcheeFrame { expr } = self.top_frame();
+                    self.pop_frame();
+                    self.push_frame(MatchFrame { expr, index: ValueRefT::from(0isize),
+                                                 matchee: value });
+                    self.try_case()
+                },
+                CaseFrame::TAG => {
+                    let &CaseFrame { case } = self.top_frame();
+                    self.commit(case.lex_defs(), case.dyn_defs())?;
+                    self.pop_frame();
+                    self.push_frame(GuardFrame { case });
+                    self.control = case.guard();
+                    Ok(State::Eval)
+                },
+                GuardFrame::TAG => {
+                    let &GuardFrame { case } = self.top_frame();
+                    match value.try_unbox::<bool>() {
+                        Some(true) => {
+                            self.pop_frame(); // GuardFrame
+                            self.pop_frame(); // MatchFrame
+                            self.control = case.body();
+                            Ok(State::Eval)
+                        },
+                        Some(false) => {
+                            self.pop_frame();
+                            self.next_case()
+                        },
+                        None => Err(EvalError::Type { expected: "Bool", received: value })
+                    }
+                },
+                PatsFrame::TAG => {
+                    let &PatsFrame { pats, index, outer } = self.top_frame();
+                    let seq: ValueRefT<Slice> = unsafe { value.downcast() };
+                    let new_index = index.unbox() as usize + 1;
+                    if new_index < subpatterns(pats).len() {
+                        self.top_frame_mut::<PatsFrame>().index = (new_index as isize).into();
+                        self.control = subpatterns(pats)[new_index];
+                        Ok(State::Parse(seq))
+                    } else {
+                        self.pop_frame();
+                        Ok(State::Continue(outer.into()))
+                    }
+                },
+                ViewFrame::TAG => {
+                    let &ViewFrame { pats, value: matchee, outer } = self.top_frame();
+                    self.pop_frame();
+                    self.push_frame(UnapplyFrame { pats, outer });
+                    let mut callee = value;
+                    let mut matchee = matchee;
+                    let unapply_name = allocate!(Symbol::new, ("unapply"),
+                                                 {self, callee, matchee})?;
+                    let unapply = initialized(unapply_name,
+                                              self.lenv.unwrap().get(unapply_name)?)?;
+                    let args = allocate!(Tuple::new, (2, vec![callee, matchee].into_iter()),
+                                         {self, callee, matchee})?;
+                    self.apply(unapply, &[unapply, ValueRefT::from(0isize).into(), args.into()])
+                },
+                UnapplyFrame::TAG => {
+                    let &UnapplyFrame { pats, outer } = self.top_frame();
+                    self.pop_frame();
+                    match value.try_downcast::<Tuple>() {
+                        Some(components) => self.parse_subpatterns(pats.into(), components, outer),
+                        None => Ok(State::Mismatch(Mismatch::View))
+                    }
+                },
+                CallFrame::TAG => {
+                    self.stack.push(Some(value));
+                    let &CallFrame { call, index } = self.top_frame();
+                    let new_index = index.unbox() as usize + 1;
+                    if new_index <= call.args().len() {
+                        self.top_frame_mut::<CallFrame>().index = (new_index as isize).into();
+                        self.control = call.args()[new_index - 1];
+                        Ok(State::Eval)
+                    } else {
+                        let vals: Vec<ValueRef> = self.frame_values::<CallFrame>().iter()
+                                                      .map(|v| v.unwrap())
+                                                      .collect();
+                        self.pop_frame();
+                        self.control = call.into(); // for the position of errors
+                        self.apply(vals[0], &vals[1..])
+                    }
+                },
+                PrimCallFrame::TAG => {
+                    self.stack.push(Some(value));
+                    let &PrimCallFrame { call, index } = self.top_frame();
+                    let new_index = index.unbox() as usize + 1;
+                    if new_index < call.args().len() {
+                        self.top_frame_mut::<PrimCallFrame>().index = (new_index as isize).into();
+                        self.control = call.args()[new_index];
+                        Ok(State::Eval)
+                    } else {
+                        let vals: Vec<ValueRef> = self.frame_values::<PrimCallFrame>().iter()
+                                                      .map(|v| v.unwrap())
+                                                      .collect();
+                        self.control = call.into(); // for the position of errors
+                        self.primapply(call.op(), &vals)
+                    }
+                },
+                PromptFrame::TAG => {
+                    self.prompt = self.outer_prompt(self.fp);
+                    self.pop_frame();
+                    Ok(State::Continue(value))
+                },
+                RequireFrame::TAG => {
+                    let &RequireFrame { file } = self.top_frame();
+                    self.modules.insert(PathBuf::from(file.chars()), value);
+                    self.pop_frame();
+                    Ok(State::Continue(value))
+                },
+                FoldFrame::TAG => {
+                    let &FoldFrame { f, entries, stride, index } = self.top_frame();
+                    let (stride, i) = (stride.unbox() as usize, index.unbox() as usize);
+                    if (i + 1) * stride <= entries.vals().len() {
+                        self.top_frame_mut::<FoldFrame>().index = (i as isize + 1).into();
+                        let mut vals = vec![value];
+                        vals.extend_from_slice(&entries.vals()[i * stride..(i + 1) * stride]);
+                        let args = allocate!(Tuple::new, (vals.len(), vals.iter().cloned()),
+                                             {self, value})?;
+                        self.apply(f, &[f, ValueRefT::from(0isize).into(), args.into()])
+                    } else {
+                        self.pop_frame();
+                        Ok(State::Continue(value))
+                    }
+                },
                 // {
                 //     let (name, env) = typecase!(self.top_frame::<VarFrame>().0, {
                 //         lvar: Lex => (lvar.name(), self.lenv),
@@ -256,36 +916,613 @@ impl Interpreter {
                 //         return Err(EvalError::Type);
                 //     }
                 // },
-                _ => unimplemented!()
+                _ => Err(EvalError::Internal("unknown stack frame"))
             }
         } else {
             Ok(State::Halt(value))
         }
     }
 
+    /// Apply `callee` to `args`. The value is returned to the frame on top of the stack.
+    ///
+    /// Functions take the closure itself, a method index and the argument tuple, as laid out by
+    /// `CstFactory::function`.
+    fn apply(&mut self, callee: ValueRef, args: &[ValueRef]) -> EvalResult<State> {
+        typecase!(callee, {
+            mut closure: Closure => {
+                let code = closure.code();
+                if code.params().len() != args.len() {
+                    return Err(EvalError::Argc { expected: code.params().len(),
+                                                 received: args.len() });
+                }
+
+                // A closure only has the one set of methods, so if `CstFactory::function` falls
+                // through to the next method index, none of them were applicable:
+                match args[1].try_unbox::<isize>() {
+                    Some(0) => {},
+                    Some(_) => return Err(EvalError::NoMethod),
+                    None => return Err(EvalError::Type { expected: "Int", received: args[1] })
+                }
+
+                // Keep `args` alive while allocating:
+                let sp = self.stack.len();
+                self.stack.extend(args.iter().map(|&arg| Some(arg)));
+                let env = allocate!(Env::block, (closure.lenv(), code.params()), {self, closure})?;
+                self.stack.truncate(sp);
+
+                for (&name, &arg) in closure.code().params().iter().zip(args) {
+                    env.init(name, arg)?;
+                }
+
+                self.lenv = Some(env);
+                self.code = Some(closure.code());
+                self.control = closure.code().body();
+                Ok(State::Eval)
+            },
+            k: Continuation => {
+                primops::argc(args, 3)?;
+                let vals = primops::tuple(args[2])?.vals();
+                primops::argc(vals, 1)?;
+                let value = self.reinstate(k, vals[0])?;
+                Ok(State::Continue(value))
+            },
+            k: OneShot => {
+                primops::argc(args, 3)?;
+                let vals = primops::tuple(args[2])?.vals();
+                primops::argc(vals, 1)?;
+                self.resume(k)?;
+                Ok(State::Continue(vals[0]))
+            },
+            _ => Err(EvalError::Type { expected: "Fn", received: callee })
+        })
+    }
+
+    /// Push a `PromptFrame` for `tag` and `handler` and call `thunk` inside it.
+    fn prompt(&mut self, tag: ValueRef, mut thunk: ValueRef, handler: ValueRef)
+        -> EvalResult<State>
+    {
+        let outer = ValueRefT::from(self.prompt.map_or(-1, |pfp| pfp as isize));
+        self.push_frame(PromptFrame { tag, handler, outer });
+        self.prompt = Some(self.fp);
+        let unit = allocate!(Tuple::new, (0, iter::empty()), {self, thunk})?;
+        self.apply(thunk, &[thunk, ValueRefT::from(0isize).into(), unit.into()])
+    }
+
+    /// Evaluate the module `name` (unless it has been already) in the environment of the prelude
+    /// and return its value. Relative to the file of `self.control` (the `__require` call), if
+    /// any.
+    fn require(&mut self, name: &str) -> EvalResult<State> {
+        let requirer = ast::source_pos(self.control).map(|pos| PathBuf::from(&*pos.file));
+        let file = self.modules.resolve(name, requirer.as_ref().map(PathBuf::as_path))
+                       .ok_or_else(|| EvalError::NoModule(name.to_string()))?;
+        if let Some(value) = self.modules.get(&file) {
+            return Ok(State::Continue(value));
+        }
+        self.check_cycle(&file)?;
+
+        let program = module::parse(&file)?;
+        let file_name = file.to_string_lossy();
+        let mut file = allocate!(String::new, (&file_name), {self})?;
+        let program = allocate!(inject_program, (&program), {self, file})?;
+        self.push_frame(RequireFrame { file });
+        self.lenv = self.modules.prelude();
+        self.code = None;
+        self.control = program;
+        Ok(State::Eval)
+    }
+
+    /// Check that the module in `file` is not already being evaluated.
+    fn check_cycle(&self, file: &Path) -> EvalResult<()> {
+        let mut requirers = Vec::new();
+        if !self.stack.is_empty() {
+            let mut fp = self.fp;
+            loop {
+                if self.frame_tag(fp) == RequireFrame::TAG {
+                    let frame = unsafe { transmute::<_, &RequireFrame>(&self.stack[fp + 7]) };
+                    requirers.push(frame.file.chars().to_string());
+                    if Path::new(frame.file.chars()) == file {
+                        requirers.reverse();
+                        requirers.push(file.to_string_lossy().into_owned());
+                        return Err(EvalError::CyclicRequire(requirers));
+                    }
+                }
+                if fp == 0 {
+                    break;
+                }
+                fp = self.caller_fp(fp);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unwind to the innermost `__try` and call its handler with `value`. Unlike `abort` this
+    /// does not capture a continuation.
+    fn raise(&mut self, mut value: ValueRef) -> EvalResult<State> {
+        let pfp = match self.find_prompt(self.exn_tag.into()) {
+            Some(pfp) => pfp,
+            None => return Err(EvalError::Panic(value))
+        };
+
+        self.fp = pfp;
+        self.restore_envs();
+        let &PromptFrame { mut handler, .. } = self.top_frame();
+        self.prompt = self.outer_prompt(pfp);
+        self.pop_frame();
+
+        let args = allocate!(Tuple::new, (1, iter::once(value)), {self, value, handler})?;
+        self.apply(handler, &[handler, ValueRefT::from(0isize).into(), args.into()])
+    }
+
+    /// Turn `err` into an exception `(kind, message, position)` and `raise` it if there is an
+    /// enclosing `__try`. The position `(file, line, column)` is that of `self.control`.
+    fn raise_error(&mut self, err: EvalError) -> EvalResult<State> {
+        match err {
+            EvalError::OOM | EvalError::Panic(_) => return Err(err),
+            _ => {}
+        }
+        if self.find_prompt(self.exn_tag.into()).is_none() {
+            return Err(err);
+        }
+
+        let mut kind = allocate!(Symbol::new, (err.kind()), {self})?;
+        let mut message = allocate!(String::new, (&err.to_string()), {self, kind})?;
+        let mut pos = match ast::pos(self.control) {
+            Some(pos) => {
+                let (file, line, col) = (pos.file.chars().to_string(), pos.line, pos.col);
+                let mut file = allocate!(String::new, (&file), {self, kind, message})?;
+                allocate!(Tuple::new, (3, vec![file.into(), line.into(), col.into()].into_iter()),
+                          {self, kind, message, file})?
+            },
+            None => allocate!(Tuple::new, (0, iter::empty()), {self, kind, message})?
+        };
+        let exn = allocate!(Tuple::new, (3, vec![kind.into(), message.into(), pos.into()]
+                                                .into_iter()),
+                            {self, kind, message, pos})?;
+        self.raise(exn.into())
+    }
+
+    /// Unwind to the innermost prompt tagged with `tag`, capturing the frames above it, and call
+    /// the handler of the prompt with the continuation and `value`. If `one_shot` is set the
+    /// frames are captured into a `OneShot` instead of a `Continuation`.
+    fn abort(&mut self, tag: ValueRef, mut value: ValueRef, one_shot: bool) -> EvalResult<State> {
+        let pfp = match self.find_prompt(tag) {
+            Some(pfp) => pfp,
+            None => return Err(EvalError::NoPrompt(tag))
+        };
+        let base = pfp + 7 + usize::from(GSize::of::<PromptFrame>());
+        let top = self.detach_frames(base);
+
+        let mut k: ValueRef = if one_shot {
+            allocate!(OneShot::new, (top, &self.stack[base..]), {self, value})?.into()
+        } else {
+            allocate!(Continuation::new, (top, &self.stack[base..]), {self, value})?.into()
+        };
+
+        self.stack.truncate(base);
+        self.fp = pfp;
+        self.restore_envs();
+        let &PromptFrame { mut handler, .. } = self.top_frame();
+        self.prompt = self.outer_prompt(pfp);
+        self.pop_frame();
+
+        let args = allocate!(Tuple::new, (2, vec![k, value].into_iter()),
+                             {self, k, value, handler})?;
+        self.apply(handler, &[handler, ValueRefT::from(0isize).into(), args.into()])
+    }
+
+    /// Apply the primop `op` to `args`, which are kept alive by the `PrimCallFrame` on top of the
+    /// stack. The frame is popped before the value is returned.
+    fn primapply(&mut self, op: PrimOp, args: &[ValueRef]) -> EvalResult<State> {
+        match op {
+            PrimOp::Denv => {
+                primops::argc(args, 0)?;
+                let denv = match self.denv {
+                    Some(denv) => denv,
+                    None => allocate!(Env::empty, (None), {self})?
+                };
+                self.pop_frame();
+                Ok(State::Continue(denv.into()))
+            },
+            PrimOp::DenvGet => {
+                primops::argc(args, 2)?;
+                let denv = primops::env(args[0])?;
+                let name = primops::symbol(args[1])?;
+                let value = initialized(name, denv.get(name)?)?;
+                self.pop_frame();
+                Ok(State::Continue(value))
+            },
+            PrimOp::Prompt => {
+                primops::argc(args, 3)?;
+                self.pop_frame();
+                self.prompt(args[0], args[1], args[2])
+            },
+            PrimOp::Abort => {
+                primops::argc(args, 2)?;
+                self.pop_frame();
+                self.abort(args[0], args[1], false)
+            },
+            PrimOp::AbortOnce => {
+                primops::argc(args, 2)?;
+                self.pop_frame();
+                self.abort(args[0], args[1], true)
+            },
+            PrimOp::Raise => {
+                primops::argc(args, 1)?;
+                self.pop_frame();
+                self.raise(args[0])
+            },
+            PrimOp::Try => {
+                primops::argc(args, 2)?;
+                self.pop_frame();
+                let tag = self.exn_tag.into();
+                self.prompt(tag, args[0], args[1])
+            },
+            PrimOp::Require => {
+                primops::argc(args, 1)?;
+                let name = primops::string(args[0])?.chars().to_string();
+                self.pop_frame();
+                self.require(&name)
+            },
+            PrimOp::MapFold | PrimOp::SetFold => {
+                primops::argc(args, 3)?;
+                let (entries, stride): (Vec<ValueRef>, isize) = match op {
+                    PrimOp::MapFold => {
+                        let entries = primops::map(args[2])?.entries();
+                        (entries.into_iter().flat_map(|(key, value)| vec![key, value]).collect(), 2)
+                    },
+                    _ => (primops::set(args[2])?.elems(), 1)
+                };
+                // The arguments are kept alive by the `PrimCallFrame` until it is popped:
+                let entries = allocate!(Tuple::new, (entries.len(), entries.iter().cloned()),
+                                        {self})?;
+                self.pop_frame();
+                self.push_frame(FoldFrame { f: args[0], entries, stride: ValueRefT::from(stride),
+                                            index: ValueRefT::from(0isize) });
+                Ok(State::Continue(args[1]))
+            },
+            PrimOp::IAddS | PrimOp::ISubS | PrimOp::IMulS => {
+                primops::argc(args, 3)?;
+                let (a, b) = (primops::int(args[0])?, primops::int(args[1])?);
+                let res = match op {
+                    PrimOp::IAddS => primops::iadd(a, b),
+                    PrimOp::ISubS => primops::isub(a, b),
+                    _ => primops::imul(a, b)
+                };
+                self.pop_frame();
+                match res {
+                    Ok(n) => Ok(State::Continue(ValueRefT::from(n).into())),
+                    Err(overflow) => self.overflow(args[2], overflow)
+                }
+            },
+            PrimOp::IDivRem => {
+                primops::argc(args, 3)?;
+                let (a, b) = (primops::int(args[0])?, primops::int(args[1])?);
+                let res = primops::idiv_rem(a, b)?;
+                self.pop_frame();
+                match res {
+                    Ok((quot, rem)) => {
+                        let vals: [ValueRef; 2] = [ValueRefT::from(quot).into(),
+                                                   ValueRefT::from(rem).into()];
+                        let res = allocate!(Tuple::new, (2, vals.iter().cloned()), {self})?;
+                        Ok(State::Continue(res.into()))
+                    },
+                    Err(overflow) => self.overflow(args[2], overflow)
+                }
+            },
+            // Forcing a collection is only for the GC tests:
+            #[cfg(test)]
+            PrimOp::Collect => {
+                primops::argc(args, 0)?;
+                self.collect_garbage();
+                let unit = allocate!(Tuple::new, (0, iter::empty()), {self})?;
+                self.pop_frame();
+                Ok(State::Continue(unit.into()))
+            },
+            #[cfg(not(test))]
+            PrimOp::Collect => Err(EvalError::Internal("__collect is only available in tests")),
+            op => {
+                let res = match primops::apply_pure(&mut *Allocator::instance(), op, args) {
+                    Err(EvalError::OOM) => {
+                        self.collect_garbage();
+                        primops::apply_pure(&mut *Allocator::instance(), op, args)
+                    },
+                    res => res
+                }?;
+                self.pop_frame();
+                Ok(State::Continue(res))
+            }
+        }
+    }
+
+    /// Call the `onOverflow` continuation `k` of an arithmetic primop with the components of
+    /// `overflow`.
+    fn overflow(&mut self, mut k: ValueRef, overflow: primops::Overflow) -> EvalResult<State> {
+        let components = overflow.components();
+        let args = allocate!(Tuple::new, (components.len(),
+                                         components.iter().map(|&n| ValueRefT::from(n).into())),
+                             {self, k})?;
+        self.apply(k, &[k, ValueRefT::from(0isize).into(), args.into()])
+    }
+
+    /// Look up the dynamic variable `name`. Dynamic bindings are made by blocks and patterns for
+    /// the extent of their evaluation, so callees see the bindings of their callers. If `name` is
+    /// not bound in the current dynamic environment, the environments saved by the prompts on the
+    /// stack are searched from the innermost outwards (like `Dump.find` in the SML interpreter).
+    fn lookup_dyn(&self, name: ValueRefT<Symbol>) -> EvalResult<Option<ValueRef>> {
+        let err = match self.denv.unwrap().get(name) {
+            Ok(res) => return Ok(res),
+            Err(err) => err
+        };
+
+        let mut prompt = self.prompt;
+        while let Some(pfp) = prompt {
+            let denv: Option<ValueRefT<Env>> = unsafe { transmute(self.stack[pfp + 2]) };
+            if let Some(res) = denv.and_then(|denv| denv.get(name).ok()) {
+                return Ok(res);
+            }
+            prompt = self.outer_prompt(pfp);
+        }
+
+        Err(err.into())
+    }
+
+    /// Push the frames captured in `k` so that the bottom one returns to the current top frame and
+    /// return `value`, which is kept alive meanwhile.
+    ///
+    /// Since an earlier reinstatement of `k` may have initialized the variables that the frames
+    /// have yet to initialize, those are bound to new promises. The `CommitFrame`s and
+    /// `CaseFrame`s also get copies of the environment buffers that they are filling.
+    fn reinstate(&mut self, k: ValueRefT<Continuation>, mut value: ValueRef)
+        -> EvalResult<ValueRef>
+    {
+        let base = self.stack.len();
+        self.stack.extend_from_slice(k.slots());
+        let binders = self.binding_frames(base, k.top());
+        self.attach_frames(base, k.top());
+
+        for &fp in &binders {
+            if self.frame_tag(fp) != BlockFrame::TAG {
+                for i in fp + 3..fp + 5 {
+                    let buf = allocate!(EnvBuffer::copy, (unsafe { transmute(self.stack[i]) }),
+                                        {self, value})?;
+                    let orig = self.stack[i];
+                    for slot in self.stack[base..].iter_mut().filter(|slot| **slot == orig) {
+                        *slot = Some(buf.into());
+                    }
+                }
+            }
+            for j in 0..self.uncommitted(fp).len() {
+                let promise = allocate!(Promise::new, (), {self, value})?;
+                let (i, name) = self.uncommitted(fp)[j];
+                let mut env: ValueRefT<Env> = unsafe { transmute(self.stack[i]) };
+                env.rebind(unsafe { name.downcast() }, promise)?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Push the frames captured in `k` like `reinstate` and clear them from `k`. As the frames
+    /// can only run once, they keep their environment buffers and promises.
+    fn resume(&mut self, mut k: ValueRefT<OneShot>) -> EvalResult<()> {
+        let base = self.stack.len();
+        let top = k.take(&mut self.stack).ok_or(EvalError::ResumedTwice)?;
+        self.attach_frames(base, top);
+        Ok(())
+    }
+
+    /// Make the saved frame pointers (and `PromptFrame` links) of the frames above `base` relative
+    /// to `base`, with -1 for the ones that point below it, and return the index of the top frame
+    /// relative to `base` (or 0 if there are no frames above `base`).
+    fn detach_frames(&mut self, base: usize) -> usize {
+        if base == self.stack.len() {
+            return 0;
+        }
+
+        let relative = |fp: isize| ValueRefT::from(if fp >= base as isize {
+            fp - base as isize
+        } else {
+            -1
+        });
+        let mut fp = self.fp;
+        while fp >= base {
+            if self.frame_tag(fp) == PromptFrame::TAG {
+                let outer = self.prompt_frame(fp).outer.unbox();
+                self.prompt_frame_mut(fp).outer = relative(outer);
+            }
+            let old_fp = self.caller_fp(fp);
+            self.stack[fp] = relative(old_fp as isize).as_root();
+            fp = old_fp;
+        }
+        self.fp - base
+    }
+
+    /// Undo `detach_frames` for frames that have been pushed at `base` so that the bottom one
+    /// returns to the current top frame and the outermost `PromptFrame` among them links to the
+    /// current innermost one. Then make the frame at `base + top` the top frame.
+    fn attach_frames(&mut self, base: usize, top: usize) {
+        if base == self.stack.len() {
+            return;
+        }
+
+        let (caller, outer) = (self.fp as isize, self.prompt.map_or(-1, |pfp| pfp as isize));
+        let absolute = |fp: isize, default: isize| ValueRefT::from(if fp < 0 {
+            default
+        } else {
+            base as isize + fp
+        });
+        let mut fp = base + top;
+        let mut innermost = None;
+        loop {
+            if self.frame_tag(fp) == PromptFrame::TAG {
+                innermost = innermost.or(Some(fp));
+                let link = self.prompt_frame(fp).outer.unbox();
+                self.prompt_frame_mut(fp).outer = absolute(link, outer);
+            }
+            let old_fp = self.caller_fp(fp) as isize;
+            self.stack[fp] = absolute(old_fp, caller).as_root();
+            if old_fp < 0 {
+                break;
+            }
+            fp = base + old_fp as usize;
+        }
+        self.fp = base + top;
+        self.prompt = innermost.or(self.prompt);
+    }
+
+    /// The frame pointers of the `BlockFrame`s, `CommitFrame`s and `CaseFrame`s among the frames
+    /// that `detach_frames` left at `base`, with the top one at `base + top`.
+    fn binding_frames(&self, base: usize, top: usize) -> Vec<usize> {
+        let mut binders = Vec::new();
+        if base == self.stack.len() {
+            return binders;
+        }
+
+        let mut fp = base + top;
+        loop {
+            match self.frame_tag(fp) {
+                BlockFrame::TAG | CommitFrame::TAG | CaseFrame::TAG => binders.push(fp),
+                _ => {}
+            }
+            let old_fp: ValueRefT<isize> = unsafe { self.stack[fp].unwrap().downcast() };
+            if old_fp.unbox() < 0 {
+                return binders;
+            }
+            fp = base + old_fp.unbox() as usize;
+        }
+    }
+
+    /// The variables that the frame at `fp` has yet to initialize (those of the current and later
+    /// statements of a `BlockFrame`), each paired with the header slot of the environment that
+    /// binds it.
+    fn uncommitted(&self, fp: usize) -> Vec<(usize, ValueRef)> {
+        let mut vars = Vec::new();
+        {
+            let mut add = |lex_defs: ValueRefT<Tuple>, dyn_defs: ValueRefT<Tuple>| {
+                vars.extend(lex_defs.vals().iter().map(|&name| (fp + 1, name)));
+                vars.extend(dyn_defs.vals().iter().map(|&name| (fp + 2, name)));
+            };
+            match self.frame_tag(fp) {
+                BlockFrame::TAG => {
+                    let &BlockFrame { block, index } = self.frame(fp);
+                    for &stmt in &block.stmts()[index.unbox() as usize..] {
+                        if let Some(def) = stmt.try_downcast::<Def>() {
+                            add(def.lex_defs(), def.dyn_defs());
+                        }
+                    }
+                },
+                CommitFrame::TAG => {
+                    let &CommitFrame { lex_defs, dyn_defs } = self.frame(fp);
+                    add(lex_defs, dyn_defs);
+                },
+                CaseFrame::TAG => {
+                    let case = self.frame::<CaseFrame>(fp).case;
+                    add(case.lex_defs(), case.dyn_defs());
+                },
+                _ => {}
+            }
+        }
+        vars
+    }
+
+    /// The frame pointer of the innermost `PromptFrame` tagged with `tag`.
+    fn find_prompt(&self, tag: ValueRef) -> Option<usize> {
+        let mut prompt = self.prompt;
+        while let Some(pfp) = prompt {
+            if self.prompt_frame(pfp).tag == tag {
+                return Some(pfp);
+            }
+            prompt = self.outer_prompt(pfp);
+        }
+        None
+    }
+
+    /// The frame pointer of the `PromptFrame` enclosing the one at `pfp`.
+    fn outer_prompt(&self, pfp: usize) -> Option<usize> {
+        let outer = self.prompt_frame(pfp).outer.unbox();
+        if outer < 0 { None } else { Some(outer as usize) }
+    }
+
+    fn frame<T: SubFrame>(&self, fp: usize) -> &T {
+        unsafe { transmute::<_, &T>(&self.stack[fp + 7]) }
+    }
+
+    fn prompt_frame(&self, pfp: usize) -> &PromptFrame {
+        unsafe { transmute::<_, &PromptFrame>(&self.stack[pfp + 7]) }
+    }
+
+    fn prompt_frame_mut(&mut self, pfp: usize) -> &mut PromptFrame {
+        unsafe { transmute::<_, &mut PromptFrame>(&mut self.stack[pfp + 7]) }
+    }
+
+    /// The function and the position of the AST node of the current state and each frame on the
+    /// stack (that has an AST node), from the top down.
+    fn backtrace(&self) -> Vec<TraceFrame> {
+        fn frame(code: Option<ValueRefT<Function>>, node: Option<ValueRef>) -> TraceFrame {
+            TraceFrame {
+                name: match code {
+                    Some(code) if !code.name().chars().is_empty() =>
+                        code.name().chars().to_string(),
+                    Some(_) => "<anonymous>".to_string(),
+                    None => "<toplevel>".to_string()
+                },
+                pos: node.and_then(ast::source_pos)
+            }
+        }
+
+        let mut backtrace = vec![frame(self.code, Some(self.control))];
+        if !self.stack.is_empty() {
+            let mut fp = self.fp;
+            loop {
+                let node = self.stack[fp + 7];
+                if node.and_then(ast::pos).is_some() {
+                    backtrace.push(frame(unsafe { transmute(self.stack[fp + 5]) }, node));
+                }
+                if fp == 0 {
+                    break;
+                }
+                fp = self.caller_fp(fp);
+            }
+        }
+        backtrace
+    }
+
     fn restore_envs(&mut self) {
         self.lenv = unsafe { transmute(self.stack[self.fp + 1]) };
         self.denv = unsafe { transmute(self.stack[self.fp + 2]) };
         self.lenv_buf = unsafe { transmute(self.stack[self.fp + 3]) };
         self.denv_buf = unsafe { transmute(self.stack[self.fp + 4]) };
+        self.code = unsafe { transmute(self.stack[self.fp + 5]) };
     }
 
-    fn top_frame_tag(&self) -> usize {
-        unsafe { transmute(self.stack[self.fp + 5]) }
+    fn top_frame_tag(&self) -> usize { self.frame_tag(self.fp) }
+
+    fn frame_tag(&self, fp: usize) -> usize {
+        unsafe { transmute(self.stack[fp + 6]) }
+    }
+
+    /// The (saved) frame pointer of the caller of the frame at `fp`.
+    fn caller_fp(&self, fp: usize) -> usize {
+        let old_fp: ValueRefT<isize> = unsafe { self.stack[fp].unwrap().downcast() };
+        old_fp.unbox() as usize
     }
 
     fn top_frame<T: SubFrame>(&self) -> &T {
-        unsafe { transmute::<_, &T>(&self.stack[self.fp + 6]) }
+        unsafe { transmute::<_, &T>(&self.stack[self.fp + 7]) }
     }
 
     fn top_frame_mut<T: SubFrame>(&mut self) -> &mut T {
-        unsafe { transmute::<_, &mut T>(&mut self.stack[self.fp + 6]) }
+        unsafe { transmute::<_, &mut T>(&mut self.stack[self.fp + 7]) }
+    }
+
+    /// The values that have been pushed on the stack above the fields of the top frame.
+    fn frame_values<T: SubFrame>(&self) -> &[Option<ValueRef>] {
+        &self.stack[self.fp + 7 + usize::from(GSize::of::<T>())..]
     }
 
     fn pop_frame(&mut self) {
-        let new_fp: ValueRefT<isize> = unsafe { self.stack[self.fp].unwrap().downcast() };
+        let new_fp = self.caller_fp(self.fp);
         self.stack.truncate(self.fp);
-        self.fp = new_fp.unbox() as _;
+        self.fp = new_fp;
     }
 
     fn push_frame<T: SubFrame>(&mut self, subframe: T) {
@@ -296,6 +1533,7 @@ impl Interpreter {
         self.stack.push(self.denv.as_root());
         self.stack.push(self.lenv_buf.as_root());
         self.stack.push(self.denv_buf.as_root());
+        self.stack.push(self.code.as_root());
 
         self.stack.push(unsafe { transmute(T::TAG) });
         let ptr = &subframe as *const T as *const Option<ValueRef>;
@@ -305,14 +1543,622 @@ impl Interpreter {
         }
     }
 
+    fn collect_garbage(&mut self) {
+        let mut heap = Allocator::instance();
+        self.mark_roots(&mut heap);
+        unsafe { heap.collect_garbage(); }
+    }
+
     fn mark_roots(&mut self, heap: &mut Allocator) {
         unsafe {
             self.control = transmute(heap.mark_ref(self.control.into()));
-            self.lenv = transmute(heap.mark_ref(self.control.into()));
-            self.denv = transmute(heap.mark_ref(self.control.into()));
+            self.lenv = transmute(heap.mark_ref(self.lenv.as_root()));
+            self.denv = transmute(heap.mark_ref(self.denv.as_root()));
+            self.lenv_buf = transmute(heap.mark_ref(self.lenv_buf.as_root()));
+            self.denv_buf = transmute(heap.mark_ref(self.denv_buf.as_root()));
+            self.code = transmute(heap.mark_ref(self.code.as_root()));
         }
         for slot in self.stack.iter_mut() {
             *slot = heap.mark_ref(*slot);
         }
+        self.modules.mark_roots(heap);
+        unsafe { self.exn_tag = transmute(heap.mark_ref(self.exn_tag.as_root())); }
+    }
+}
+
+// ================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use pcws_domain::Allocator;
+    use pcws_domain::object_model::{Unbox, ValueRef, ValueRefT};
+    use pcws_domain::values::{Tuple, String, Symbol, Integer};
+    use pcws_syntax::cst::{Expr, Pattern, PrimOp, Const, CstFactory, Pos};
+    use bootstrap;
+    use inject::Inject;
+    use env::Env;
+    use module::Modules;
+    use repl::Repl;
+    use ast::{self, Block};
+    use super::{interpret, EvalResult, EvalError, RuntimeError};
+
+    fn run(program: Expr) -> Result<ValueRef, RuntimeError> {
+        run_in(program, &mut Modules::default())
+    }
+
+    fn run_in(program: Expr, modules: &mut Modules) -> Result<ValueRef, RuntimeError> {
+        ::register_types();
+        let (ast, denv) = {
+            let heap = &mut *Allocator::instance();
+            (bootstrap::with_apply(program).inject(heap).unwrap(),
+             bootstrap::host_denv(heap, &["foo".to_string()]).unwrap())
+        };
+        interpret(ast, None, denv, modules)
+    }
+
+    /// Write the module `files` (paths and sources) into the temporary directory `dir` and run
+    /// `src` with `dir` as the module search path.
+    fn run_modules(dir: &str, files: &[(&str, &str)], src: &str) -> EvalResult<ValueRef> {
+        let dir = ::std::env::temp_dir().join(dir);
+        for &(path, src) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(src.as_bytes()).unwrap();
+        }
+        run_in(Expr::from_str(src).unwrap(), &mut Modules::new(vec![dir]))
+            .map_err(|err| err.error)
+    }
+
+    fn eval(program: Expr) -> EvalResult<ValueRef> { run(program).map_err(|err| err.error) }
+
+    fn eval_str(src: &str) -> EvalResult<ValueRef> { eval(Expr::from_str(src).unwrap()) }
+
+    fn int(n: isize) -> ValueRef { ValueRefT::from(n).into() }
+
+    #[test]
+    fn primcall_args() {
+        assert_eq!(eval_str("__iAdd (__iAdd 1 2) 3").unwrap(), int(6));
+    }
+
+    #[test]
+    fn closures() {
+        let res = eval_str("adder = {n => {x => __iAdd x n}};
+                            add2 = adder 2;
+                            add3 = adder 3;
+                            __tuple (add2 1) (add3 1) adder add2").unwrap();
+        let res = res.try_downcast::<Tuple>().unwrap();
+        assert_eq!(&res.vals()[..2], &[int(3), int(4)]);
+        assert_eq!(res.vals()[2].to_string(), "#<fn adder>");
+        assert_eq!(res.vals()[3].to_string(), "#<fn>");
+    }
+
+    #[test]
+    fn closure_gc() {
+        ::in_own_process("interpret::tests::closure_gc", || {
+            let res = eval_str("f = {n => {x => __tuple x n}};
+                                g = f (__tuple 1 2);
+                                __collect;
+                                junk = __tuple (__tuple 3 4) (__tuple 5 6);
+                                g 0");
+            assert_eq!(res.unwrap().to_string(), "(0, (1, 2))");
+        });
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(eval_str("f = {a b => __iSub a b}; f (f 10 3) 2").unwrap(), int(5));
+        // Every call goes through the `apply` in scope:
+        assert_eq!(eval_str("{apply = {f args => __tuple f args}; 1 2 3}").unwrap().to_string(),
+                   "(1, (2, 3))");
+        match eval_str("1 2") {
+            Err(EvalError::Type { expected: "Fn", received }) => assert_eq!(received, int(1)),
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn guards() {
+        let res = eval_str("sign = {n | __iLt n 0 => 0; n | __iGt n 0 => 2; n => 1};
+                            __tuple (sign (__iSub 0 5)) (sign 5) (sign 0)").unwrap();
+        assert_eq!(res.try_downcast::<Tuple>().unwrap().vals(), &[int(0), int(2), int(1)]);
+        match eval_str("{n | __iLt n 0 => n} 5") {
+            Err(EvalError::NoMethod) => {},
+            res => panic!("{:?}", res)
+        }
+        match eval_str("{n | n => n} 5") {
+            Err(EvalError::Type { expected: "Bool", received }) => assert_eq!(received, int(5)),
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn view_patterns() {
+        let res = eval_str("half = {n => __tuple (__iDiv n 2)};
+                            f = {(half h) | __iGt h 1 => h; n => 0};
+                            __tuple (f 6) (f 2)").unwrap();
+        assert_eq!(res.try_downcast::<Tuple>().unwrap().vals(), &[int(3), int(0)]);
+        // An explicit `apply` call is a view pattern like any other:
+        assert!(Expr::from_str("{(apply a b c) => a}").is_ok());
+    }
+
+    #[test]
+    fn slice_gc() {
+        ::in_own_process("interpret::tests::slice_gc", || {
+            let res = eval_str("g = {x => {__collect; __tuple x}};
+                                f = {(g a) b c => __tuple a b c};
+                                f 1 (__tuple 2) (__tuple 3)");
+            assert_eq!(res.unwrap().to_string(), "(1, (2), (3))");
+        });
+    }
+
+    #[test]
+    fn denv() {
+        assert!(eval_str("__denv").unwrap().try_downcast::<Env>().is_some());
+    }
+
+    #[test]
+    fn denv_get() {
+        let factory = CstFactory::new(Pos::default());
+        let program = factory.block(
+            vec![factory.def(Pattern::Dyn(Pos::default(), "x".to_string()), factory.constant(5))],
+            factory.primcall(PrimOp::DenvGet,
+                             vec![factory.primcall(PrimOp::Denv, vec![]),
+                                  factory.constant(Const::Symbol("x".to_string()))])
+        );
+        assert_eq!(eval(program).unwrap(), int(5));
+    }
+
+    #[test]
+    fn bigint_literal() {
+        let n = eval_str("__iSub 100000000000000000000 1").unwrap();
+        assert_eq!(Integer::from_value(n), Integer::parse("99999999999999999999"));
+    }
+
+    #[test]
+    fn overflow_continuation() {
+        assert_eq!(eval_str("__iAddS 1 2 {n => n}").unwrap(), int(3));
+        assert_eq!(eval_str("__iMulS 1099511627776 1099511627776 {hi lo => hi}").unwrap(),
+                   int(1 << 20));
+        let quot_rem = eval_str("__iDivRem 7 2 {hi lo => 0}").unwrap();
+        assert_eq!(quot_rem.try_downcast::<Tuple>().unwrap().vals(), &[int(3), int(1)]);
+    }
+
+    #[test]
+    fn dynamic_extent() {
+        let res = eval_str("$x = 1; f = {y => $x}; a = {$x = 2; f 0}; __tuple a (f 0)").unwrap();
+        assert_eq!(res.try_downcast::<Tuple>().unwrap().vals(), &[int(2), int(1)]);
+    }
+
+    #[test]
+    fn host_denv() {
+        let args = eval_str("$Std.Process.arguments").unwrap().try_downcast::<Tuple>().unwrap();
+        assert_eq!(args.vals()[0].try_downcast::<String>().unwrap().chars(), "foo");
+    }
+
+    #[test]
+    fn abort() {
+        assert_eq!(eval_str("__prompt 0 [__iAdd 1 (__abort 0 2)] {k v => v}").unwrap(), int(2));
+        match eval_str("__prompt 0 [__abort 1 2] {k v => v}") {
+            Err(EvalError::NoPrompt(tag)) => assert_eq!(tag, int(1)),
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn multishot_continuation() {
+        let res = eval_str("k = __prompt 0 [__iAdd 10 (__abort 0 0)] {c v => c};
+                            __tuple (k 1) (k 2)").unwrap();
+        assert_eq!(res.try_downcast::<Tuple>().unwrap().vals(), &[int(11), int(12)]);
+    }
+
+    #[test]
+    fn multishot_definitions() {
+        let res = eval_str("__prompt 0 [x = __abort 0 1; x] {k v => __tuple (k 1) (k 2)}");
+        assert_eq!(res.unwrap().to_string(), "(1, 2)");
+        // Closures made before the capture see the variables bound by the latest reinstatement:
+        let res = eval_str("__prompt 0 [g = {u => x}; x = __abort 0 0; g 0]
+                                       {k v => __tuple (k 1) (k 2)}");
+        assert_eq!(res.unwrap().to_string(), "(1, 2)");
+        // Each reinstatement finishes matching the pattern from where the capture left it:
+        let res = eval_str("ask = {x => __tuple (__abort 0 x)};
+                            f = {a (ask b) => __tuple a b};
+                            __prompt 0 [f 1 0] {k v => __tuple (k 2) (k 3)}");
+        assert_eq!(res.unwrap().to_string(), "((1, 2), (1, 3))");
+    }
+
+    #[test]
+    fn continuation_gc() {
+        ::in_own_process("interpret::tests::continuation_gc", || {
+            let res = eval_str("k = __prompt 0 [x = __tuple 1 2; __tuple x (__abort 0 0)]
+                                             {c v => c};
+                                __collect;
+                                junk = __tuple (__tuple 3) (__tuple 4 5);
+                                k 6");
+            assert_eq!(res.unwrap().to_string(), "((1, 2), 6)");
+        });
+    }
+
+    #[test]
+    fn nested_prompts() {
+        let res = eval_str("__prompt 0 [__iAdd 1 (__prompt 1 [__iAdd 10 (__abort 0 5)] {c v => v})]
+                                      {c v => __iAdd 100 (c v)}").unwrap();
+        assert_eq!(res, int(116));
+    }
+
+    #[test]
+    fn reinstated_denv() {
+        let res = eval_str("k = __prompt 0 [f = {u => $z}; __iAdd 0 (f (__abort 0 0))] {c v => c};
+                            {$z = 7; __prompt 1 [k 0] 0}").unwrap();
+        assert_eq!(res, int(7));
+    }
+
+    #[test]
+    fn oneshot_continuation() {
+        let res = eval_str("k = __prompt 0 [__iAdd 10 (__abortOnce 0 0)] {c v => c}; k 1");
+        assert_eq!(res.unwrap(), int(11));
+        match eval_str("k = __prompt 0 [__iAdd 10 (__abortOnce 0 0)] {c v => c};
+                        __tuple (k 1) (k 2)") {
+            Err(EvalError::ResumedTwice) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn oneshot_gc() {
+        ::in_own_process("interpret::tests::oneshot_gc", || {
+            let res = eval_str("k = __prompt 0 [x = __tuple 1 2; __tuple x (__abortOnce 0 0)]
+                                             {c v => c};
+                                __collect;
+                                junk = __tuple (__tuple 3) (__tuple 4 5);
+                                k 6");
+            assert_eq!(res.unwrap().to_string(), "((1, 2), 6)");
+            match eval_str("k = __prompt 0 [__iAdd 10 (__abortOnce 0 0)] {c v => c};
+                            k 1; __collect; k 2") {
+                Err(EvalError::ResumedTwice) => {},
+                res => panic!("{:?}", res)
+            }
+        });
+    }
+
+    /// A generator of the integers below `n` that yields them with the primop `abort` to a
+    /// handler that sums them up.
+    fn generator_sum(abort: &str, n: usize) -> ::std::string::String {
+        format!("loop = {{i | __iLt i {n} => {{{abort} 0 i; loop (__iAdd i 1)}}; i => 0}};
+                 h = {{k v => __iAdd v (__prompt 0 [k 0] h)}};
+                 __prompt 0 [loop 0] h", abort = abort, n = n)
+    }
+
+    #[test]
+    fn generator() {
+        assert_eq!(eval_str(&generator_sum("__abort", 100)).unwrap(), int(4950));
+        assert_eq!(eval_str(&generator_sum("__abortOnce", 100)).unwrap(), int(4950));
+    }
+
+    #[test]
+    fn reinstated_prompt() {
+        let res = eval_str("__prompt 0 [__prompt 1 [__iAdd 1 (__abort 1 (__abort 0 0))]
+                                                   {c v => __iAdd v 100}]
+                                      {k v => k 5}").unwrap();
+        assert_eq!(res, int(105));
+    }
+
+    /// Evaluate `src` with the prelude and the exports of `lib/core/effects.pcws` in scope.
+    fn eval_effects(src: &str) -> EvalResult<ValueRef> {
+        ::register_types();
+        let mut repl = Repl::new(Modules::default(), true).map_err(|err| err.error)?;
+        let src = format!("@require core.effects {{newEffect, perform, performMulti, handleWith,
+                                                   handle}};
+                           {}", src);
+        repl.eval(Expr::from_str(&src).unwrap()).map_err(|err| err.error)
+    }
+
+    #[test]
+    fn state_effect() {
+        let res = eval_effects("st = newEffect \"state\";
+                                get = [perform st (__tuple 0 0)];
+                                put = {s => perform st (__tuple 1 s)};
+                                runState = {s body =>
+                                    (handleWith st body {x => {s => __tuple x s}} {
+                                        (__tuple 0 u) resume => {s => (resume s) s};
+                                        (__tuple 1 s) resume => {t => (resume 0) s}
+                                    }) s
+                                };
+                                runState 1 [put (__iAdd (force get) 10); __iMul (force get) 2]");
+        assert_eq!(res.unwrap().try_downcast::<Tuple>().unwrap().vals(), &[int(22), int(11)]);
+    }
+
+    #[test]
+    fn exception_effect() {
+        let res = eval_effects("exn = newEffect \"exn\";
+                                raise = {e => perform exn e};
+                                try = {body handler => handle exn body {e resume => handler e}};
+                                __tuple (try [__iAdd 1 (raise 41)] {e => __iAdd e 1})
+                                        (try [5] {e => 0})");
+        assert_eq!(res.unwrap().try_downcast::<Tuple>().unwrap().vals(), &[int(42), int(5)]);
+    }
+
+    #[test]
+    fn nondeterminism_effect() {
+        let res = eval_effects("amb = newEffect \"amb\";
+                                choose = {a b => {c | __eq c 0 => a; c => b} (performMulti amb 0)};
+                                handle amb [__iAdd (choose 1 2) (choose 10 20)]
+                                       {op resume => __iAdd (resume 0) (resume 1)}");
+        assert_eq!(res.unwrap(), int(66));
+    }
+
+    #[test]
+    fn generator_effect() {
+        let res = eval_effects("gen = newEffect \"gen\";
+                                numbers = {i | __iLe i 3 => {perform gen i; numbers (__iAdd i 1)};
+                                           i => 0};
+                                handle gen [numbers 1]
+                                       {i resume => __iAdd (__iMul i i) (resume 0)}");
+        assert_eq!(res.unwrap(), int(14));
+    }
+
+    #[test]
+    fn unhandled_effect() {
+        match eval_effects("perform (newEffect \"eff\") 0") {
+            Err(EvalError::NoPrompt(_)) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn raise() {
+        assert_eq!(eval_str("__try [__iAdd 1 (__raise 5)] {e => __iAdd e 1}").unwrap(), int(6));
+        assert_eq!(eval_str("__try [3] {e => 0}").unwrap(), int(3));
+        match eval_str("__raise 5") {
+            Err(EvalError::Panic(value)) => assert_eq!(value, int(5)),
+            res => panic!("{:?}", res)
+        }
+    }
+
+    /// The kind, message and (line, column) of the exception `exn`.
+    fn exception(exn: ValueRef) -> (::std::string::String, ::std::string::String, isize, isize) {
+        let exn = exn.try_downcast::<Tuple>().unwrap();
+        let pos = exn.vals()[2].try_downcast::<Tuple>().unwrap();
+        (exn.vals()[0].try_downcast::<Symbol>().unwrap().chars().to_string(),
+         exn.vals()[1].try_downcast::<String>().unwrap().chars().to_string(),
+         pos.vals()[1].try_unbox::<isize>().unwrap(), pos.vals()[2].try_unbox::<isize>().unwrap())
+    }
+
+    #[test]
+    fn runtime_exceptions() {
+        let (kind, _, line, col) = exception(eval_str("__try [__iAdd 1 (__tuple)] {e => e}")
+                                                 .unwrap());
+        assert_eq!((kind.as_str(), line, col), ("type", 1, 8));
+
+        let exn = exception(eval_str("__try [__iAdd 1 x] {e => e}").unwrap());
+        assert_eq!(exn, ("unbound".to_string(), "unbound variable x".to_string(), 1, 17));
+    }
+
+    #[test]
+    fn node_positions() {
+        ::register_types();
+        let program = Expr::from_str("f = {x => x};\n__try [f 1 2] {e => e}").unwrap();
+        let node = program.inject(&mut *Allocator::instance()).unwrap();
+        let (outer, inner) = (ast::pos(node).unwrap(),
+                              ast::pos(node.try_downcast::<Block>().unwrap().expr()).unwrap());
+        assert_eq!(ValueRef::from(outer.file), ValueRef::from(inner.file));
+        assert_eq!((outer.file.chars(), inner.line.unbox(), inner.col.unbox()), ("", 2, 1));
+
+        let exn = eval_str("__try [__iAdd 1 (__tuple)] {e => e}").unwrap();
+        assert_eq!(exn.try_downcast::<Tuple>().unwrap().vals()[2].to_string(), "(\"\", 1, 8)");
+    }
+
+    #[test]
+    fn uninitialized() {
+        let exn = exception(eval_str("__try [x = y; y = 1; x] {e => e}").unwrap());
+        assert_eq!(exn, ("uninitialized".to_string(),
+                         "variable y read before initialization".to_string(), 1, 12));
+
+        let (kind, message, _, _) = exception(eval_str("__try [x = 1; x = 2; x] {e => e}")
+                                                  .unwrap());
+        assert_eq!((kind.as_str(), message.as_str()), ("reinit", "variable x initialized twice"));
+
+        match eval_str("x = y; y = 1; x") {
+            Err(EvalError::Uninitialized(name)) => assert_eq!(name.chars(), "y"),
+            res => panic!("expected Uninitialized, got {:?}", res)
+        }
+    }
+
+    #[test]
+    fn records() {
+        let res = eval_str("Pair = __newRecordType \"Pair\" 2;
+                            pair = {a b =>
+                                __endRecord (__recordInit (__recordInit (__beginRecord Pair 2)
+                                                                        0 a) 1 b)
+                            };
+                            p = pair 1 (pair 2 3);
+                            __tuple p (__eq (__type p) Pair)
+                                    (__eq (__type p) (__newRecordType \"Pair\" 2))
+                                    (__sliceGetP (__recordSlice p) 0)");
+        let res = res.unwrap().try_downcast::<Tuple>().unwrap();
+        assert_eq!(res.vals()[0].to_string(), "Pair(1, Pair(2, 3))");
+        assert_eq!(&res.vals()[1..], &[ValueRefT::from(true).into(), ValueRefT::from(false).into(),
+                                       int(1)]);
+    }
+
+    #[test]
+    fn record_type_gc() {
+        ::in_own_process("interpret::tests::record_type_gc", || {
+            // A fresh symbol is not interned, so only the type keeps its name alive:
+            let body = Expr::from_str("T = __newRecordType (__symbolFresh $name) 1;
+                                       p = __endRecord (__recordInit (__beginRecord T 1) 0 5);
+                                       __collect;
+                                       junk = __tuple (__tuple 1 2) (__tuple 3 4);
+                                       __tuple T p").unwrap();
+            let factory = CstFactory::new(Pos::default());
+            let program = factory.block(
+                vec![factory.def(Pattern::Dyn(Pos::default(), "name".to_string()),
+                                 factory.constant(Const::Symbol("Point".to_string())))],
+                body
+            );
+            assert_eq!(eval(program).unwrap().to_string(), "(Point, Point(5))");
+        });
+    }
+
+    #[test]
+    fn maps() {
+        let res = eval_str("m = {1 -> 10, 2 -> 20, (__tuple 3) -> 30};
+                            __tuple (__mapFold {acc k v => __iAdd acc v} 0 m)
+                                    (__mapGet (__mapInsert m 4 40) 4)
+                                    (__mapGet m (__tuple 3))
+                                    (__mapLen (__mapRemove m 2))
+                                    (__setFold {acc x => __iAdd acc x} 0 (__set 1 2 3 2))
+                                    {->}
+                                    (__try [__mapGet m 5] {e => e})");
+        let res = res.unwrap().try_downcast::<Tuple>().unwrap();
+        assert_eq!(&res.vals()[..5], &[int(60), int(40), int(30), int(2), int(6)]);
+        assert_eq!(res.vals()[5].to_string(), "{->}");
+        let (kind, message, _, _) = exception(res.vals()[6]);
+        assert_eq!((kind.as_str(), message.as_str()), ("noKey", "key 5 not found"));
+    }
+
+    #[test]
+    fn map_gc() {
+        ::in_own_process("interpret::tests::map_gc", || {
+            // Enough entries for several levels of trie nodes:
+            let res = eval_str("fill = {
+                                    m i | __iLt i 1000 =>
+                                        fill (__mapInsert m (__tuple i) (__tuple i)) (__iAdd i 1);
+                                    m _ => m
+                                };
+                                m = fill {->} 0;
+                                __collect;
+                                junk = __tuple (__tuple 1 2) (__tuple 3 4);
+                                __tuple (__mapLen m) (__mapGet m (__tuple 0))
+                                        (__mapGet m (__tuple 999))
+                                        (__mapFold {acc k v => __iAdd acc (__tupleGet v 0)} 0 m)");
+            assert_eq!(res.unwrap().to_string(), "(1000, (0), (999), 499500)");
+        });
+    }
+
+    #[test]
+    fn strings() {
+        let res = eval_str("s = __stringConcat \"foo\" \"bar\";
+                            __tuple (__stringLen s) (__stringSub s 1 4) (__stringToSymbol s)
+                                    (__toString (__rope (__rope \"a\" \"b\") (__toString 42)))");
+        assert_eq!(res.unwrap().to_string(), "(6, \"oob\", :foobar, \"ab42\")");
+    }
+
+    #[test]
+    fn byte_buffers() {
+        let res = eval_str("buf = __byteBuffer;
+                            __bufferAppend (__bufferPush buf 104) \"äj\";
+                            __tuple (__utf8Decode (__bufferBytes buf))
+                                    (__try [__utf8Decode (__bytesSet (__bytes 1) 0 255)]
+                                           {e => e})");
+        let res = res.unwrap().try_downcast::<Tuple>().unwrap();
+        assert_eq!(res.vals()[0].to_string(), "\"häj\"");
+        let (kind, message, _, _) = exception(res.vals()[1]);
+        assert_eq!((kind.as_str(), message.as_str()), ("invalidUtf8", "invalid UTF-8 at byte 0"));
+    }
+
+    #[test]
+    fn structural_constants() {
+        let res = eval_str("f = {\"foo\" => 1; x => 2};
+                            __tuple (f \"foo\") (f \"bar\")
+                                    (__equal (__tuple \"a\" 1) (__tuple \"a\" 1))");
+        assert_eq!(res.unwrap().try_downcast::<Tuple>().unwrap().vals(),
+                   &[int(1), int(2), ValueRefT::from(true).into()]);
+    }
+
+    #[test]
+    fn backtrace() {
+        let src = "f = {x => __iAdd x (__tuple)};\ng = {y => __iAdd 1 (f y)};\ng 1";
+        let err = run(Expr::from_str(src).unwrap()).unwrap_err();
+        assert_eq!(err.pos.map(|pos| (pos.line, pos.col)), Some((1, 11)));
+        let frames = err.backtrace.iter()
+                        .map(|frame| (frame.name.as_str(), frame.pos.as_ref().map(|pos| pos.line)))
+                        .collect::<Vec<_>>();
+        assert_eq!(frames[0], ("f", Some(1)));
+        assert!(frames[1..].contains(&("g", Some(2))));
+    }
+
+    #[test]
+    fn modules() {
+        let files = [("Util/Ids.pcws", "id = {x => x};\nanswer = 42;\n@export {id, answer}")];
+        let res = run_modules("pcws-modules", &files,
+                              "@require Util.Ids;
+                               @require Util.Ids {answer => n};
+                               @require Util.Ids.(id);
+                               __tuple (__eq Ids.id id) n (__eq (@require \"Util.Ids\").id id)
+                                       (__try [Ids.nope] {e => e})");
+        let res = res.unwrap().try_downcast::<Tuple>().unwrap();
+        assert_eq!(&res.vals()[..3], &[ValueRefT::from(true).into(), int(42),
+                                       ValueRefT::from(true).into()]);
+        let (kind, message, _, _) = exception(res.vals()[3]);
+        assert_eq!((kind.as_str(), message.as_str()), ("notExported", "nope is not exported"));
+    }
+
+    #[test]
+    fn import_forms() {
+        // The library modules parse, and so does every `@require` in the examples:
+        let sources = [include_str!("../../../lib/__Bootstrap/HAMT.pcws"),
+                       include_str!("../../../lib/__Bootstrap/List.pcws"),
+                       include_str!("../../../lib/__Bootstrap/ListTable.pcws"),
+                       include_str!("../../../lib/__Bootstrap/Tuple.pcws"),
+                       include_str!("../../../lib/__Bootstrap/TupleTable.pcws"),
+                       include_str!("../../../lib/__Bootstrap/Types.pcws"),
+                       include_str!("../../../lib/core/core.pcws"),
+                       include_str!("../../../lib/core/effects.pcws")];
+        for src in sources.iter() {
+            assert!(Expr::from_str(src).is_ok(), "{}", src);
+        }
+        for stmt in include_str!("../../../example/cat.pcws").split(';') {
+            if stmt.contains("@require") {
+                assert!(Expr::from_str(&format!("{};\n0", stmt)).is_ok(), "{}", stmt);
+            }
+        }
+
+        // The imports of `example/cat.pcws` against stand-ins for the modules it requires:
+        let files = [("Std.pcws", "SyncIO = 1;\n@export {SyncIO}"),
+                     ("Std/File.pcws", "2"),
+                     ("Std/Fn.pcws", "(|>) = {x f => f x};\n@export {(|>)}"),
+                     ("Std/Category.pcws", "(>>>) = {f g => {x => g (f x)}};\n@export {(>>>)}")];
+        let header = include_str!("../../../example/cat.pcws").split("\n\n").next().unwrap();
+        let src = format!("{}\n(|>) 3 ((>>>) {{x => __tuple IO File x}} {{y => __tuple y}})",
+                          header);
+        let res = run_modules("pcws-import-forms", &files, &src);
+        assert_eq!(res.unwrap().to_string(), "((1, 2, 3))");
+
+        // Destructuring, both of export records and of modules whose value is a tuple:
+        let files = [("Pair.pcws", "first = 1;\nsecond = 2;\n@export {first, second}"),
+                     ("Triple.pcws", "(3, 4, 5)")];
+        let res = run_modules("pcws-import-destructuring", &files,
+                              "(a, _) = @require \"Pair\";
+                               (_, b, _) = @require \"Triple\";
+                               (c,) = (@require \"Pair\", );
+                               @require \"Triple\";
+                               __tuple a b c.second Triple");
+        assert_eq!(res.unwrap().to_string(), "(1, 4, 2, (3, 4, 5))");
+    }
+
+    #[test]
+    fn module_errors() {
+        let files = [("A.pcws", "@require B;\n1"), ("B.pcws", "@require A;\n2")];
+        let res = run_modules("pcws-module-cycle", &files,
+                              "__tuple (__try [@require A] {e => e})
+                                       (__try [@require C] {e => e})");
+        let res = res.unwrap().try_downcast::<Tuple>().unwrap();
+        let (kind, message, _, _) = exception(res.vals()[0]);
+        assert_eq!(kind, "cyclicRequire");
+        let files = message["cyclic module dependency: ".len()..].split(" -> ")
+                        .map(|file| file.rsplit('/').next().unwrap())
+                        .collect::<Vec<_>>();
+        assert_eq!(files, &["A.pcws", "B.pcws", "A.pcws"]);
+        let (kind, message, _, _) = exception(res.vals()[1]);
+        assert_eq!((kind.as_str(), message.as_str()), ("noModule", "module C not found"));
+    }
+
+    #[test]
+    fn prompt() {
+        assert_eq!(eval_str("__prompt 0 [__iAdd 1 2] 0").unwrap(), int(3));
     }
 }
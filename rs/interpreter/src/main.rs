@@ -11,55 +11,341 @@ extern crate pcws_syntax;
 mod ast;
 mod inject;
 mod env;
+mod closure;
+mod continuation;
+mod bootstrap;
 mod interpret;
+mod primops;
+mod module;
+mod repl;
 
-use std::str::FromStr;
+use std::fs::File;
 use std::io::{self, Read};
+use std::process;
+use std::path::PathBuf;
+use std::sync::{Once, ONCE_INIT};
 
-use pcws_domain::{Allocator, register_static_t};
-use pcws_domain::values;
+use pcws_domain::{Allocator, register_static_t, register_builtin_types};
+use pcws_domain::object_model::{ValueRef, ValueRefT};
 use pcws_syntax::cst::Expr;
 use env::{Env, EnvBuffer};
+use closure::Closure;
+use continuation::{Continuation, OneShot};
 use inject::Inject;
 use interpret::interpret;
+use module::{Modules, Exports};
+use repl::Repl;
 
-fn main() {
-    register_static_t::<values::Promise>();
-    register_static_t::<values::Tuple>();
-    register_static_t::<values::Slice>();
-    register_static_t::<values::String>();
-    register_static_t::<values::Symbol>();
-    register_static_t::<ast::Function>();
-    register_static_t::<ast::Block>();
-    register_static_t::<ast::Match>();
-    register_static_t::<ast::Case>();
-    register_static_t::<ast::Def>();
-    register_static_t::<ast::Call>();
-    register_static_t::<ast::PrimCall>();
-    register_static_t::<ast::Lex>();
-    register_static_t::<ast::Dyn>();
-    register_static_t::<ast::Const>();
-    register_static_t::<Env>();
-    register_static_t::<EnvBuffer>();
+/// Register the heap value types used by the interpreter (once).
+fn register_types() {
+    static REGISTER: Once = ONCE_INIT;
+
+    REGISTER.call_once(|| {
+        register_builtin_types();
+        register_static_t::<ast::Function>("Function");
+        register_static_t::<ast::Block>("Block");
+        register_static_t::<ast::Match>("Match");
+        register_static_t::<ast::Case>("Case");
+        register_static_t::<ast::Def>("Def");
+        register_static_t::<ast::Call>("Call");
+        register_static_t::<ast::PrimCall>("PrimCall");
+        register_static_t::<ast::Lex>("Lex");
+        register_static_t::<ast::Dyn>("Dyn");
+        register_static_t::<ast::Const>("Const");
+        register_static_t::<Env>("Env");
+        register_static_t::<EnvBuffer>("EnvBuffer");
+        register_static_t::<Closure>("Fn");
+        register_static_t::<Continuation>("Continuation");
+        register_static_t::<OneShot>("OneShot");
+        register_static_t::<Exports>("Exports");
+    });
+}
+
+/// Run the test `name` (its path in the crate) in a process of its own. Tests share the heap but
+/// a collection only marks the roots of the interpreter that collects, so a test that collects
+/// garbage would free the values of the tests running concurrently with it.
+#[cfg(test)]
+fn in_own_process<F: FnOnce()>(name: &str, test: F) {
+    if ::std::env::var_os("PCWS_OWN_PROCESS").is_some() {
+        test();
+    } else {
+        let status = ::std::process::Command::new(::std::env::current_exe().unwrap())
+                                             .args(&[name, "--exact", "--test-threads", "1"])
+                                             .env("PCWS_OWN_PROCESS", "1")
+                                             .status().unwrap();
+        assert!(status.success(), "{} failed in its own process", name);
+    }
+}
 
+const USAGE: &str = "\
+usage: pcws [--heap-size <size>] [--lib <dir>]... [--no-prelude] <command>
+
+commands:
+    run <file> [args...]  run the program in <file>, passing it `args`
+    check <file>          check that the program in <file> parses and loads
+    dump-cst <file>       print the concrete syntax tree of the program in <file>
+    dump-ast <file>       print the abstract syntax tree of the program in <file>
+    repl                  start an interactive session
+    help                  print this message
+
+<file> can be `-` to read the program from stdin. <size> is the maximum heap size in bytes,
+optionally suffixed with K, M or G. Required modules are looked up in the directory of the
+requiring file, then in the library directory and the directories given with `--lib`. The library
+directory is `$PCWS_LIB` if it is set, else the nearest `lib` with the `__Bootstrap` modules in or
+above the directory of the pcws executable. Programs and REPL sessions start with the exports of
+the prelude (`__Bootstrap` and `core/core.pcws` in the library) in scope unless `--no-prelude` is
+given.";
+
+// Exit codes (from BSD `sysexits.h`, apart from `EXIT_ERROR`):
+
+const EXIT_OK: i32 = 0;
+/// The program raised an uncaught runtime error.
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 64;
+/// The program could not be parsed.
+const EXIT_DATAERR: i32 = 65;
+/// The program file could not be read.
+const EXIT_NOINPUT: i32 = 66;
+/// The program could not be loaded into the heap or the prelude failed to evaluate.
+const EXIT_SOFTWARE: i32 = 70;
+/// Reading or writing the REPL session failed.
+const EXIT_IOERR: i32 = 74;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(String, Vec<String>),
+    Check(String),
+    DumpCst(String),
+    DumpAst(String),
+    Repl,
+    Help
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    heap_size: Option<usize>,
+    /// The module search path.
+    search_path: Vec<PathBuf>,
+    /// Whether to evaluate the prelude before user code.
+    prelude: bool,
+    command: Command
+}
+
+/// Parse the command line `args` (without the program name).
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    let mut heap_size = None;
+    let mut search_path = vec![module::lib_dir()];
+    let mut prelude = true;
+    loop {
+        let arg = args.next().ok_or_else(|| "missing command".to_string())?;
+        let command = match arg.as_str() {
+            "--heap-size" => {
+                let size = args.next().ok_or_else(|| "missing heap size".to_string())?;
+                heap_size = Some(parse_size(&size)
+                                     .ok_or_else(|| format!("invalid heap size {}", size))?);
+                continue;
+            },
+            "--lib" => {
+                let dir = args.next().ok_or_else(|| "missing library directory".to_string())?;
+                search_path.push(PathBuf::from(dir));
+                continue;
+            },
+            "--no-prelude" => {
+                prelude = false;
+                continue;
+            },
+            "run" => {
+                let file = args.next().ok_or_else(|| "missing file".to_string())?;
+                Command::Run(file, args.collect())
+            },
+            "check" | "dump-cst" | "dump-ast" => {
+                let file = args.next().ok_or_else(|| "missing file".to_string())?;
+                if let Some(arg) = args.next() {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                match arg.as_str() {
+                    "check" => Command::Check(file),
+                    "dump-cst" => Command::DumpCst(file),
+                    _ => Command::DumpAst(file)
+                }
+            },
+            "repl" => {
+                if let Some(arg) = args.next() {
+                    return Err(format!("unexpected argument {}", arg));
+                }
+                Command::Repl
+            },
+            "help" | "--help" | "-h" => Command::Help,
+            _ => return Err(format!("unknown command {}", arg))
+        };
+        return Ok(Options { heap_size, search_path, prelude, command });
+    }
+}
+
+/// Parse a byte count with an optional K, M or G suffix.
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1)
+    };
+    digits.parse::<usize>().ok().and_then(|n| n.checked_mul(unit))
+}
+
+/// Read the source code in `file` (or stdin if `file` is `-`).
+fn read_source(file: &str) -> io::Result<String> {
     let mut src = String::new();
-    io::stdin().read_to_string(&mut src).unwrap();
+    if file == "-" {
+        io::stdin().read_to_string(&mut src)?;
+    } else {
+        File::open(file)?.read_to_string(&mut src)?;
+    }
+    Ok(src)
+}
 
-    match Expr::from_str(&src) {
-        Ok(program) => {
-            println!("{}", program);
+/// Read and parse the program in `file`, reporting errors as exit codes.
+fn parse(file: &str) -> Result<Expr, i32> {
+    let src = read_source(file).map_err(|err| {
+        eprintln!("pcws: cannot read {}: {}", file, err);
+        EXIT_NOINPUT
+    })?;
+    Expr::parse(&src, if file == "-" { "<stdin>" } else { file }).map_err(|err| {
+        eprintln!("pcws: cannot parse {}: {:?}", file, err);
+        EXIT_DATAERR
+    })
+}
 
-            println!("\n---\n");
+/// Convert `program` to its heap representation, reporting errors as exit codes.
+fn load(program: Expr) -> Result<ValueRef, i32> {
+    program.inject(&mut *Allocator::instance()).map(Into::into).ok_or_else(out_of_memory)
+}
 
-            let ast = program.inject(&mut *Allocator::instance()).unwrap(); // FIXME: unwrap
+fn out_of_memory() -> i32 {
+    eprintln!("pcws: out of memory while loading the program");
+    EXIT_SOFTWARE
+}
 
-            // println!("{}", ast);
-            //
-            // println!("\n---\n");
+/// Evaluate the prelude in `denv` (if `prelude` is true), reporting errors as exit codes.
+fn prelude(prelude: bool, denv: ValueRefT<Env>, modules: &mut Modules)
+    -> Result<Option<ValueRefT<Env>>, i32>
+{
+    if !prelude {
+        return Ok(None);
+    }
+    bootstrap::prelude(denv, modules).map_err(|err| {
+        eprintln!("pcws: cannot load the prelude: {}", err);
+        EXIT_SOFTWARE
+    })
+}
 
-            let value = interpret(ast).unwrap();
-            println!("{}", value);
+fn execute(command: Command, mut modules: Modules, use_prelude: bool) -> Result<(), i32> {
+    match command {
+        Command::Run(file, arguments) => {
+            let program = bootstrap::with_apply(parse(&file)?);
+            let denv = bootstrap::host_denv(&mut *Allocator::instance(), &arguments)
+                           .ok_or_else(out_of_memory)?;
+            let lenv = prelude(use_prelude, denv, &mut modules)?;
+            // Only load the program now, since evaluating the prelude may collect garbage:
+            let ast = load(program)?;
+            match interpret(ast, lenv, denv, &mut modules) {
+                Ok(value) => {
+                    println!("{}", value);
+                    Ok(())
+                },
+                Err(err) => {
+                    eprintln!("{}", err);
+                    Err(EXIT_ERROR)
+                }
+            }
+        },
+        Command::Check(file) => load(bootstrap::with_apply(parse(&file)?)).map(|_| ()),
+        Command::DumpCst(file) => {
+            println!("{}", parse(&file)?);
+            Ok(())
+        },
+        Command::DumpAst(file) => {
+            println!("{}", load(parse(&file)?)?);
+            Ok(())
         },
-        Err(err) => println!("ParseError: {:?}", err)
+        Command::Repl => {
+            let mut repl = Repl::new(modules, use_prelude).map_err(|err| {
+                eprintln!("{}", err);
+                EXIT_SOFTWARE
+            })?;
+            let stdin = io::stdin();
+            repl.run(stdin.lock(), &mut io::stdout()).map_err(|err| {
+                eprintln!("pcws: {}", err);
+                EXIT_IOERR
+            })
+        },
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("pcws: {}\n\n{}", msg, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    if let Some(heap_size) = options.heap_size {
+        Allocator::set_max_heap(heap_size);
+    }
+    register_types();
+
+    let modules = Modules::new(options.search_path);
+    process::exit(match execute(options.command, modules, options.prelude) {
+        Ok(()) => EXIT_OK,
+        Err(code) => code
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_args, parse_size, Options, Command};
+    use module;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn command_line() {
+        assert_eq!(args("run cat.pcws foo -v"),
+                   Ok(Options {
+                       heap_size: None,
+                       search_path: vec![module::lib_dir()],
+                       prelude: true,
+                       command: Command::Run("cat.pcws".to_string(),
+                                             vec!["foo".to_string(), "-v".to_string()])
+                   }));
+        assert_eq!(args("--heap-size 16M --lib std --no-prelude dump-ast -"),
+                   Ok(Options { heap_size: Some(16 << 20),
+                                search_path: vec![module::lib_dir(), PathBuf::from("std")],
+                                prelude: false,
+                                command: Command::DumpAst("-".to_string()) }));
+        assert!(args("").is_err());
+        assert!(args("check").is_err());
+        assert!(args("check a b").is_err());
+        assert!(args("--heap-size lots run a").is_err());
+        assert!(args("frobnicate a").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("4k"), Some(4096));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("-1"), None);
     }
 }
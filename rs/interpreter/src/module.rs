@@ -0,0 +1,164 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use pcws_domain::Allocator;
+use pcws_domain::object_model::{RefTailed, ValueRef, ValueRefT};
+use pcws_domain::values::{Tuple, Symbol};
+use pcws_syntax::cst::Expr;
+use env::Env;
+use interpret::EvalError;
+use bootstrap;
+
+// ================================================================================================
+
+/// Export record (the value of `@export {a, b}`), which maps the exported names to their values
+heap_struct! {
+    pub struct Exports: RefTailed<TailItem=ValueRef> {
+        names: ValueRefT<Tuple>
+    }
+}
+
+impl Exports {
+    /// Create an export record that maps the symbols in `names` to the corresponding `values`.
+    pub fn new(allocator: &mut Allocator, names: ValueRefT<Tuple>, values: &[ValueRef])
+        -> Option<ValueRefT<Exports>>
+    {
+        allocator.create_with_slice(|base| Exports { base, names }, values)
+    }
+
+    pub fn names(&self) -> &[ValueRef] { self.names.vals() }
+
+    pub fn values(&self) -> &[ValueRef] { self.tail() }
+
+    /// The value exported as `name`, if any.
+    pub fn get(&self, name: ValueRefT<Symbol>) -> Option<ValueRef> {
+        let name = ValueRef::from(name);
+        self.names().iter().position(|&exported| exported == name).map(|i| self.values()[i])
+    }
+}
+
+impl Debug for Exports {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Exports")
+         .field("base", &self.base)
+         .field("names", &self.names)
+         .field("values", &self.values())
+         .finish()
+    }
+}
+
+impl Display for Exports {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str("@export {")?;
+        let mut names = self.names().iter();
+        if let Some(name) = names.next() {
+            write!(f, "{}", name)?;
+        }
+        for name in names {
+            write!(f, ", {}", name)?;
+        }
+        f.write_str("}")
+    }
+}
+
+// ================================================================================================
+
+/// The module search path and the values of the modules that have been evaluated.
+///
+/// The module `Std.File` (or `"Std.File"`) is the file `Std/File.pcws` in the directory of the
+/// file that requires it, else among the modules built into the binary (see
+/// `bootstrap::embedded_source`) or else in the first directory of the search path that has it.
+/// Modules are evaluated in the lexical environment of the prelude (once it has been loaded).
+#[derive(Debug)]
+pub struct Modules {
+    search_path: Vec<PathBuf>,
+    loaded: HashMap<PathBuf, ValueRef>,
+    prelude: Option<ValueRefT<Env>>
+}
+
+impl Default for Modules {
+    /// Search only `lib_dir()`.
+    fn default() -> Modules { Modules::new(vec![lib_dir()]) }
+}
+
+impl Modules {
+    pub fn new(search_path: Vec<PathBuf>) -> Modules {
+        Modules { search_path, loaded: HashMap::new(), prelude: None }
+    }
+
+    /// The file that defines the module `name` when it is required from `requirer` (the file
+    /// containing the `@require`, if any).
+    pub fn resolve(&self, name: &str, requirer: Option<&Path>) -> Option<PathBuf> {
+        let mut relative: PathBuf = name.split('.').collect();
+        relative.set_extension("pcws");
+        requirer.and_then(Path::parent).into_iter()
+                .chain(iter::once(Path::new(bootstrap::EMBEDDED_DIR)))
+                .chain(self.search_path.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(&relative))
+                .find(|path| path.is_file() || bootstrap::embedded_source(path).is_some())
+                .map(|path| path.canonicalize().unwrap_or(path))
+    }
+
+    /// The value of the module in `file` if it has already been evaluated.
+    pub fn get(&self, file: &Path) -> Option<ValueRef> { self.loaded.get(file).cloned() }
+
+    pub fn insert(&mut self, file: PathBuf, value: ValueRef) {
+        self.loaded.insert(file, value);
+    }
+
+    /// The lexical environment that modules are evaluated in.
+    pub fn prelude(&self) -> Option<ValueRefT<Env>> { self.prelude }
+
+    pub fn set_prelude(&mut self, prelude: Option<ValueRefT<Env>>) { self.prelude = prelude; }
+
+    pub fn mark_roots(&mut self, heap: &mut Allocator) {
+        for value in self.loaded.values_mut() {
+            *value = heap.mark_ref(Some(*value)).unwrap();
+        }
+        self.prelude = self.prelude.map(|env| unsafe {
+            heap.mark_ref(Some(env.into())).unwrap().downcast()
+        });
+    }
+}
+
+/// The directory of the standard library: `$PCWS_LIB` if it is set, else the nearest `lib`
+/// directory with the `__Bootstrap` modules in or above the directory of the executable (which
+/// finds the `lib` of the source tree from `rs/target/debug` as well as one installed next to
+/// `bin`), else `lib` in the working directory.
+pub fn lib_dir() -> PathBuf {
+    if let Some(dir) = ::std::env::var_os("PCWS_LIB") {
+        return PathBuf::from(dir);
+    }
+    if let Ok(exe) = ::std::env::current_exe() {
+        let mut dir = exe.parent();
+        while let Some(parent) = dir {
+            let lib = parent.join("lib");
+            if lib.join("__Bootstrap").is_dir() {
+                return lib;
+            }
+            dir = parent.parent();
+        }
+    }
+    PathBuf::from("lib")
+}
+
+/// Read and parse the module in `file`, wrapping it with `bootstrap::with_apply`.
+pub fn parse(file: &Path) -> Result<Expr, EvalError> {
+    let name = file.to_string_lossy();
+    let src = match bootstrap::embedded_source(file) {
+        Some(src) => src.to_string(),
+        None => {
+            let mut src = String::new();
+            File::open(file).and_then(|mut f| f.read_to_string(&mut src))
+                .map_err(|err| EvalError::BadModule(name.to_string(), err.to_string()))?;
+            src
+        }
+    };
+    Expr::parse(&src, &name)
+        .map(bootstrap::with_apply)
+        .map_err(|err| EvalError::BadModule(name.to_string(), format!("{:?}", err)))
+}
@@ -0,0 +1,1293 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::cmp::Ordering;
+use std::str;
+
+use pcws_domain::{Allocator, TypeRegistry};
+use pcws_domain::equality::{structural_eq, structural_hash};
+use pcws_domain::object_model::{Unbox, ValueRef, ValueRefT};
+use pcws_domain::values::{Tuple, Slice, Symbol, String, Rope, Bytes, ByteBuffer, Promise, Integer,
+                          Float, Type, Record, Map, Set};
+use pcws_syntax::cst::PrimOp;
+
+use interpret::{EvalError, EvalResult};
+use env::Env;
+use closure::Closure;
+use continuation::{Continuation, OneShot};
+use module::Exports;
+
+// ================================================================================================
+
+/// Apply one of the primops that only depend on their arguments (i.e. not the ones that call an
+/// overflow continuation or `Denv`, `DenvGet`, `Require`, `Collect`, the folds `MapFold` and
+/// `SetFold` and the control operators `Prompt`, `Abort`, `AbortOnce`, `Raise` and `Try`, which
+/// the interpreter handles itself).
+///
+/// The caller is responsible for keeping `args` alive. If this returns `EvalError::OOM` it may
+/// collect garbage and try again.
+pub fn apply_pure(heap: &mut Allocator, op: PrimOp, args: &[ValueRef]) -> EvalResult<ValueRef> {
+    match op {
+        PrimOp::Tuple =>
+            Tuple::new(heap, args.len(), args.iter().cloned())
+                 .map(From::from).ok_or(EvalError::OOM),
+        PrimOp::TupleLen => {
+            argc(args, 1)?;
+            Ok(len(tuple(args[0])?.vals().len()))
+        },
+        PrimOp::TupleGet => {
+            argc(args, 2)?;
+            let tuple = tuple(args[0])?;
+            Ok(tuple.vals()[index(args[1], tuple.vals().len())?])
+        },
+        PrimOp::TupleSlice => {
+            argc(args, 1)?;
+            let tuple = tuple(args[0])?;
+            Slice::new(heap, tuple, 0, tuple.vals().len()).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::SliceLen => {
+            argc(args, 1)?;
+            Ok(len(slice(args[0])?.len()))
+        },
+        PrimOp::SliceGetP => {
+            argc(args, 2)?;
+            let slice = slice(args[0])?;
+            Ok(slice.vals()[index(args[1], slice.len())?])
+        },
+        PrimOp::SliceSubP => {
+            argc(args, 3)?;
+            let slice = slice(args[0])?;
+            let (start, end) = range(args[1], args[2], slice.len())?;
+            Slice::sub(heap, slice, start, end).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::IAdd | PrimOp::ISub | PrimOp::IMul | PrimOp::IDiv => {
+            argc(args, 2)?;
+            if let (Some(a), Some(b)) = (args[0].try_unbox::<isize>(),
+                                         args[1].try_unbox::<isize>()) {
+                let res = match op {
+                    PrimOp::IAdd => iadd(a, b),
+                    PrimOp::ISub => isub(a, b),
+                    PrimOp::IMul => imul(a, b),
+                    _ => idiv_rem(a, b)?.map(|(quot, _)| quot)
+                };
+                if let Ok(n) = res {
+                    return Ok(boxed(n));
+                }
+            }
+
+            // Some operand or the result is a `BigInt`:
+            let (a, b) = (integer(args[0])?, integer(args[1])?);
+            let res = match op {
+                PrimOp::IAdd => &a + &b,
+                PrimOp::ISub => &a - &b,
+                PrimOp::IMul => &a * &b,
+                _ => a.div_rem(&b).ok_or(EvalError::DivByZero)?.0
+            };
+            res.to_value(heap).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::ILt | PrimOp::ILe | PrimOp::IGt | PrimOp::IGe => {
+            argc(args, 2)?;
+            let (a, b) = (integer(args[0])?, integer(args[1])?);
+            Ok(ValueRefT::from(match op {
+                PrimOp::ILt => a < b,
+                PrimOp::ILe => a <= b,
+                PrimOp::IGt => a > b,
+                _ => a >= b
+            }).into())
+        },
+
+        PrimOp::IAnd | PrimOp::IOr | PrimOp::IXor => {
+            argc(args, 2)?;
+            let (a, b) = (int(args[0])?, int(args[1])?);
+            Ok(boxed(match op {
+                PrimOp::IAnd => a & b,
+                PrimOp::IOr => a | b,
+                _ => a ^ b
+            }))
+        },
+        PrimOp::INot => {
+            argc(args, 1)?;
+            Ok(boxed(!int(args[0])?))
+        },
+        PrimOp::IShl | PrimOp::IShr | PrimOp::IShrL => {
+            argc(args, 2)?;
+            let a = int(args[0])?;
+            let n = int(args[1])?;
+            if n < 0 {
+                return Err(EvalError::Bounds { index: n, len: INT_BITS });
+            }
+            match op {
+                PrimOp::IShl => ishl(a, n as usize).map(boxed).ok_or(EvalError::Overflow),
+                PrimOp::IShr => Ok(boxed(a >> (n as usize).min(INT_BITS - 1))),
+                _ => Ok(boxed(if (n as usize) < INT_BITS {
+                    (bits(a) >> n as usize) as isize
+                } else {
+                    0
+                }))
+            }
+        },
+        PrimOp::IPopCount => {
+            argc(args, 1)?;
+            Ok(boxed(bits(int(args[0])?).count_ones() as isize))
+        },
+
+        PrimOp::FAdd | PrimOp::FSub | PrimOp::FMul | PrimOp::FDiv => {
+            argc(args, 2)?;
+            let (a, b) = (float(args[0])?, float(args[1])?);
+            Float::new(heap, match op {
+                PrimOp::FAdd => a + b,
+                PrimOp::FSub => a - b,
+                PrimOp::FMul => a * b,
+                _ => a / b
+            }).ok_or(EvalError::OOM)
+        },
+        PrimOp::FEq | PrimOp::FLt | PrimOp::FLe | PrimOp::FGt | PrimOp::FGe => {
+            argc(args, 2)?;
+            let (a, b) = (float(args[0])?, float(args[1])?);
+            Ok(ValueRefT::from(match op {
+                PrimOp::FEq => a == b,
+                PrimOp::FLt => a < b,
+                PrimOp::FLe => a <= b,
+                PrimOp::FGt => a > b,
+                _ => a >= b
+            }).into())
+        },
+        PrimOp::IToF => {
+            argc(args, 1)?;
+            Float::new(heap, integer(args[0])?.to_f64()).ok_or(EvalError::OOM)
+        },
+        PrimOp::FToI => {
+            argc(args, 1)?;
+            let n = Integer::from_f64(float(args[0])?).ok_or(EvalError::Overflow)?;
+            n.to_value(heap).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::SymbolFresh => {
+            argc(args, 1)?;
+            let name = symbol(args[0])?;
+            Symbol::fresh(heap, name.chars()).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::StringLen => {
+            argc(args, 1)?;
+            Ok(len(string(args[0])?.chars().chars().count()))
+        },
+        PrimOp::StringGet => {
+            argc(args, 2)?;
+            let s = string(args[0])?;
+            let i = index(args[1], s.chars().chars().count())?;
+            Ok(ValueRefT::from(s.chars().chars().nth(i).unwrap()).into())
+        },
+        PrimOp::StringSub => {
+            argc(args, 3)?;
+            let s = string(args[0])?;
+            let (start, end) = range(args[1], args[2], s.chars().chars().count())?;
+            let chars = s.chars();
+            let sub = &chars[byte_offset(chars, start)..byte_offset(chars, end)];
+            String::new(heap, sub).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::StringConcat => {
+            let mut chars = ::std::string::String::new();
+            for &arg in args {
+                chars.push_str(string(arg)?.chars());
+            }
+            String::new(heap, &chars).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::StringCmp => {
+            argc(args, 2)?;
+            // UTF-8 sorts like the code points it encodes:
+            Ok(boxed(match string(args[0])?.chars().cmp(string(args[1])?.chars()) {
+                Ordering::Less => -1,
+                Ordering::Equal => 0,
+                Ordering::Greater => 1
+            }))
+        },
+        PrimOp::StringChars => {
+            argc(args, 1)?;
+            let chars = string(args[0])?.chars().chars()
+                                         .map(|c| ValueRefT::from(c).into())
+                                         .collect::<Vec<ValueRef>>();
+            Tuple::new(heap, chars.len(), chars.into_iter()).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::StringToSymbol => {
+            argc(args, 1)?;
+            let name = string(args[0])?;
+            Symbol::new(heap, name.chars()).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::SymbolToString => {
+            argc(args, 1)?;
+            let name = symbol(args[0])?;
+            String::new(heap, name.chars()).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::StringToInt => {
+            argc(args, 1)?;
+            let n = Integer::parse(string(args[0])?.chars())
+                        .ok_or(EvalError::Type { expected: "Int numeral", received: args[0] })?;
+            n.to_value(heap).ok_or(EvalError::OOM)
+        },
+        PrimOp::StringToFloat => {
+            argc(args, 1)?;
+            let n = string(args[0])?.chars().parse::<f64>()
+                        .map_err(|_| EvalError::Type { expected: "Float numeral",
+                                                       received: args[0] })?;
+            Float::new(heap, n).ok_or(EvalError::OOM)
+        },
+        PrimOp::CharToInt => {
+            argc(args, 1)?;
+            Ok(boxed(character(args[0])? as isize))
+        },
+        PrimOp::IntToChar => {
+            argc(args, 1)?;
+            let n = int(args[0])?;
+            // `from_u32` rejects the surrogates in between:
+            let c = if 0 <= n && n <= 0x10_ffff { ::std::char::from_u32(n as u32) } else { None };
+            c.map(|c| ValueRefT::from(c).into())
+             .ok_or(EvalError::Type { expected: "code point", received: args[0] })
+        },
+        PrimOp::Rope => {
+            if let Some(&piece) = args.iter().find(|&&arg| !Rope::is_piece(arg)) {
+                return Err(EvalError::Type { expected: "String, Char or Rope", received: piece });
+            }
+            Rope::new(heap, args).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::ToString => {
+            argc(args, 1)?;
+            if args[0].is_instance::<String>() {
+                return Ok(args[0]);
+            }
+            String::new(heap, &to_string(args[0])).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::Bytes => {
+            argc(args, 1)?;
+            let len = int(args[0])?;
+            if len < 0 {
+                return Err(EvalError::Type { expected: "non-negative Int", received: args[0] });
+            }
+            Bytes::zeroed(heap, len as usize).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::BytesLen => {
+            argc(args, 1)?;
+            Ok(len(bytes(args[0])?.bytes().len()))
+        },
+        PrimOp::BytesGet => {
+            argc(args, 2)?;
+            let bytes = bytes(args[0])?;
+            let i = index(args[1], bytes.bytes().len())?;
+            Ok(boxed(isize::from(bytes.bytes()[i])))
+        },
+        PrimOp::BytesSet => {
+            argc(args, 3)?;
+            let mut bytes = bytes(args[0])?;
+            let i = index(args[1], bytes.bytes().len())?;
+            bytes.bytes_mut()[i] = byte(args[2])?;
+            Ok(args[0])
+        },
+        PrimOp::BytesSub => {
+            argc(args, 3)?;
+            let bytes = bytes(args[0])?;
+            let (start, end) = range(args[1], args[2], bytes.bytes().len())?;
+            Bytes::new(heap, &bytes.bytes()[start..end]).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::Utf8Encode => {
+            argc(args, 1)?;
+            let s = string(args[0])?;
+            Bytes::new(heap, s.chars().as_bytes()).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::Utf8Decode => {
+            argc(args, 1)?;
+            let bytes = bytes(args[0])?;
+            let chars = str::from_utf8(bytes.bytes())
+                            .map_err(|err| EvalError::InvalidUtf8(err.valid_up_to()))?;
+            String::new(heap, chars).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::ByteBuffer => {
+            argc(args, 0)?;
+            ByteBuffer::new(heap).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::BufferLen => {
+            argc(args, 1)?;
+            Ok(len(buffer(args[0])?.len()))
+        },
+        PrimOp::BufferPush => {
+            argc(args, 2)?;
+            let buffer = buffer(args[0])?;
+            ByteBuffer::push(heap, buffer, byte(args[1])?).ok_or(EvalError::OOM)?;
+            Ok(args[0])
+        },
+        PrimOp::BufferAppend => {
+            argc(args, 2)?;
+            let buffer = buffer(args[0])?;
+            let res = match args[1].try_downcast::<String>() {
+                Some(s) => ByteBuffer::extend(heap, buffer, s.chars().as_bytes()),
+                None => {
+                    let bytes = args[1].try_downcast::<Bytes>()
+                                       .ok_or(EvalError::Type { expected: "Bytes or String",
+                                                                received: args[1] })?;
+                    ByteBuffer::extend(heap, buffer, bytes.bytes())
+                }
+            };
+            res.ok_or(EvalError::OOM)?;
+            Ok(args[0])
+        },
+        PrimOp::BufferBytes => {
+            argc(args, 1)?;
+            let buffer = buffer(args[0])?;
+            Bytes::new(heap, buffer.bytes()).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::Promise => {
+            argc(args, 0)?;
+            Promise::new(heap).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::Redirect => {
+            argc(args, 2)?;
+            let mut promise = promise(args[0])?;
+            promise.init(args[1]).map_err(|_| EvalError::Reinit(None))?;
+            Ok(args[1])
+        },
+
+        PrimOp::Eq => {
+            argc(args, 2)?;
+            Ok(ValueRefT::from(args[0] == args[1]).into())
+        },
+        PrimOp::Equal => {
+            argc(args, 2)?;
+            Ok(ValueRefT::from(structural_eq(args[0], args[1])).into())
+        },
+        PrimOp::Hash => {
+            argc(args, 1)?;
+            let mut hasher = DefaultHasher::new();
+            structural_hash(args[0], &mut hasher);
+            // Non-negative and small enough to be an immediate Int:
+            Ok(len((hasher.finish() >> (64 - INT_BITS + 1)) as usize))
+        },
+        PrimOp::Type => {
+            argc(args, 1)?;
+            Ok(type_of(args[0]).into())
+        },
+        PrimOp::TypeName => {
+            argc(args, 1)?;
+            let name = typ(args[0])?.name().map_or_else(|| args[0].to_string(),
+                                                        |name| name.chars().to_owned());
+            String::new(heap, &name).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::BuiltinType => {
+            argc(args, 1)?;
+            let name = string(args[0])?;
+            TypeRegistry::instance().named(name.chars()).map(From::from)
+                        .ok_or(EvalError::Type { expected: "builtin type name", received: args[0] })
+        },
+
+        PrimOp::NewRecordType => {
+            argc(args, 2)?;
+            let name = match args[0].try_downcast::<String>() {
+                Some(name) => {
+                    let name = name.chars().to_owned();
+                    Symbol::new(heap, &name).ok_or(EvalError::OOM)?
+                },
+                None => symbol(args[0])?
+            };
+            let len = int(args[1])?;
+            if len < 0 {
+                return Err(EvalError::Type { expected: "non-negative Int", received: args[1] });
+            }
+            Type::record(heap, name, len as usize).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::BeginRecord => {
+            argc(args, 2)?;
+            let typ = record_type(args[0])?;
+            let len = typ.record_len().unwrap();
+            let received = int(args[1])?;
+            if received != len as isize {
+                return Err(EvalError::Argc { expected: len, received: received.max(0) as usize });
+            }
+            Record::new(heap, typ).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::RecordInit => {
+            argc(args, 3)?;
+            let mut record = record(args[0])?;
+            let i = index(args[1], record.fields().len())?;
+            record.init(i, args[2]).map_err(|_| EvalError::ReinitField(i))?;
+            Ok(args[0])
+        },
+        PrimOp::EndRecord => {
+            argc(args, 1)?;
+            fields(record(args[0])?)?;
+            Ok(args[0])
+        },
+        PrimOp::RecordSlice => {
+            argc(args, 1)?;
+            let fields = fields(record(args[0])?)?;
+            let tuple = Tuple::new(heap, fields.len(), fields.into_iter()).ok_or(EvalError::OOM)?;
+            Slice::new(heap, tuple, 0, tuple.vals().len()).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::Map => {
+            if args.len() % 2 != 0 {
+                return Err(EvalError::Argc { expected: args.len() + 1, received: args.len() });
+            }
+            let mut res = Map::new(heap).ok_or(EvalError::OOM)?;
+            for pair in args.chunks(2) {
+                res = Map::insert(heap, res, pair[0], pair[1]).ok_or(EvalError::OOM)?;
+            }
+            Ok(res.into())
+        },
+        PrimOp::MapLen => {
+            argc(args, 1)?;
+            Ok(len(map(args[0])?.len()))
+        },
+        PrimOp::MapGet => {
+            argc(args, 2)?;
+            map(args[0])?.get(args[1]).ok_or(EvalError::NoKey(args[1]))
+        },
+        PrimOp::MapHas => {
+            argc(args, 2)?;
+            Ok(ValueRefT::from(map(args[0])?.get(args[1]).is_some()).into())
+        },
+        PrimOp::MapInsert => {
+            argc(args, 3)?;
+            Map::insert(heap, map(args[0])?, args[1], args[2])
+                .map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::MapRemove => {
+            argc(args, 2)?;
+            Map::remove(heap, map(args[0])?, args[1]).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::Set => {
+            let mut res = Set::new(heap).ok_or(EvalError::OOM)?;
+            for &elem in args {
+                res = Set::insert(heap, res, elem).ok_or(EvalError::OOM)?;
+            }
+            Ok(res.into())
+        },
+        PrimOp::SetLen => {
+            argc(args, 1)?;
+            Ok(len(set(args[0])?.len()))
+        },
+        PrimOp::SetHas => {
+            argc(args, 2)?;
+            Ok(ValueRefT::from(set(args[0])?.contains(args[1])).into())
+        },
+        PrimOp::SetInsert => {
+            argc(args, 2)?;
+            Set::insert(heap, set(args[0])?, args[1]).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::SetRemove => {
+            argc(args, 2)?;
+            Set::remove(heap, set(args[0])?, args[1]).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::DenvEmpty => {
+            argc(args, 0)?;
+            Env::empty(heap, None).map(From::from).ok_or(EvalError::OOM)
+        },
+
+        PrimOp::Export => {
+            if args.len() % 2 != 0 {
+                return Err(EvalError::Argc { expected: args.len() + 1, received: args.len() });
+            }
+            let names = args.chunks(2).map(|pair| symbol(pair[0]).map(From::from))
+                            .collect::<EvalResult<Vec<ValueRef>>>()?;
+            let values = args.chunks(2).map(|pair| pair[1]).collect::<Vec<_>>();
+            let names = Tuple::new(heap, names.len(), names.into_iter()).ok_or(EvalError::OOM)?;
+            Exports::new(heap, names, &values).map(From::from).ok_or(EvalError::OOM)
+        },
+        PrimOp::Member => {
+            argc(args, 2)?;
+            let name = symbol(args[1])?;
+            exports(args[0])?.get(name).ok_or(EvalError::NotExported(name))
+        },
+
+        PrimOp::AssertP => {
+            argc(args, 1)?;
+            match args[0].try_unbox::<bool>() {
+                Some(true) => Ok(args[0]),
+                Some(false) => Err(EvalError::Assertion),
+                None => Err(EvalError::Type { expected: "Bool", received: args[0] })
+            }
+        },
+
+        PrimOp::IAddS | PrimOp::ISubS | PrimOp::IMulS | PrimOp::IDivRem
+        | PrimOp::Denv | PrimOp::DenvGet | PrimOp::Prompt | PrimOp::Abort
+        | PrimOp::AbortOnce | PrimOp::Raise | PrimOp::Try | PrimOp::Require
+        | PrimOp::Collect | PrimOp::MapFold | PrimOp::SetFold => unreachable!()
+    }
+}
+
+// ================================================================================================
+
+/// The number of bits in an immediate Int.
+const INT_BITS: usize = 61;
+
+/// The result of Int arithmetic that does not fit in an immediate Int. This is what an `onOverflow`
+/// continuation receives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// The sum or difference wrapped around to the contained Int.
+    Wrapped(isize),
+    /// The product or quotient is `hi * 2^60 + lo` where `0 <= lo < 2^60`.
+    Wide { hi: isize, lo: isize }
+}
+
+impl Overflow {
+    /// The arguments for the `onOverflow` continuation.
+    pub fn components(self) -> Vec<isize> {
+        match self {
+            Overflow::Wrapped(n) => vec![n],
+            Overflow::Wide { hi, lo } => vec![hi, lo]
+        }
+    }
+}
+
+pub fn iadd(a: isize, b: isize) -> Result<isize, Overflow> { wrapped(a + b) }
+
+pub fn isub(a: isize, b: isize) -> Result<isize, Overflow> { wrapped(a - b) }
+
+pub fn imul(a: isize, b: isize) -> Result<isize, Overflow> {
+    match a.checked_mul(b) {
+        Some(n) if ValueRefT::<isize>::checked(n).is_some() => Ok(n),
+        _ => Err(wide_mul(a, b))
+    }
+}
+
+/// The truncating quotient and remainder of `a` and `b`.
+pub fn idiv_rem(a: isize, b: isize) -> EvalResult<Result<(isize, isize), Overflow>> {
+    if b == 0 {
+        Err(EvalError::DivByZero)
+    } else if a == ValueRefT::<isize>::MIN && b == -1 {
+        Ok(Err(Overflow::Wide { hi: 1, lo: 0 }))
+    } else {
+        Ok(Ok((a / b, a % b)))
+    }
+}
+
+/// `a << n`, or `None` if bits would be lost.
+fn ishl(a: isize, n: usize) -> Option<isize> {
+    if n >= INT_BITS {
+        return if a == 0 { Some(0) } else { None };
+    }
+    let res = a << n;
+    if res >> n == a && ValueRefT::<isize>::checked(res).is_some() { Some(res) } else { None }
+}
+
+/// Fit an exact sum or difference (of two immediate Ints, so it cannot overflow `isize`) into an
+/// immediate Int.
+fn wrapped(n: isize) -> Result<isize, Overflow> {
+    if ValueRefT::<isize>::checked(n).is_some() {
+        Ok(n)
+    } else {
+        Err(Overflow::Wrapped(ValueRefT::from(n).unbox()))
+    }
+}
+
+/// The exact product of two immediate Ints as `Overflow::Wide`.
+fn wide_mul(a: isize, b: isize) -> Overflow {
+    const HALF: usize = (INT_BITS - 1) / 2;
+    const HALF_MASK: usize = (1 << HALF) - 1;
+    const LO_MASK: usize = (1 << (INT_BITS - 1)) - 1;
+
+    // Magnitudes are below 2^60, so the partial products of the 30-bit halves are below 2^60 too:
+    let (x, y) = (a.abs() as usize, b.abs() as usize);
+    let (x1, x0, y1, y0) = (x >> HALF, x & HALF_MASK, y >> HALF, y & HALF_MASK);
+    let mid = x1 * y0 + x0 * y1;
+    let lo = x0 * y0 + ((mid & HALF_MASK) << HALF);
+    let hi = (x1 * y1 + (mid >> HALF) + (lo >> (INT_BITS - 1))) as isize;
+    let lo = (lo & LO_MASK) as isize;
+
+    if (a < 0) == (b < 0) {
+        Overflow::Wide { hi, lo }
+    } else if lo == 0 {
+        Overflow::Wide { hi: -hi, lo }
+    } else {
+        Overflow::Wide { hi: -hi - 1, lo: (1 << (INT_BITS - 1)) - lo }
+    }
+}
+
+/// The two's complement bits of the immediate Int `n`.
+fn bits(n: isize) -> usize { n as usize & ((1 << INT_BITS) - 1) }
+
+fn boxed(n: isize) -> ValueRef { ValueRefT::from(n).into() }
+
+// ================================================================================================
+
+/// Check that there are exactly `expected` arguments.
+pub fn argc(args: &[ValueRef], expected: usize) -> EvalResult<()> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(EvalError::Argc { expected, received: args.len() })
+    }
+}
+
+pub fn int(v: ValueRef) -> EvalResult<isize> {
+    v.try_unbox::<isize>().ok_or(EvalError::Type { expected: "Int", received: v })
+}
+
+/// An immediate Int or `BigInt`.
+pub fn integer(v: ValueRef) -> EvalResult<Integer> {
+    Integer::from_value(v).ok_or(EvalError::Type { expected: "Int", received: v })
+}
+
+/// An immediate or heap Float.
+pub fn float(v: ValueRef) -> EvalResult<f64> {
+    Float::from_value(v).ok_or(EvalError::Type { expected: "Float", received: v })
+}
+
+pub fn tuple(v: ValueRef) -> EvalResult<ValueRefT<Tuple>> {
+    v.try_downcast::<Tuple>().ok_or(EvalError::Type { expected: "Tuple", received: v })
+}
+
+pub fn slice(v: ValueRef) -> EvalResult<ValueRefT<Slice>> {
+    v.try_downcast::<Slice>().ok_or(EvalError::Type { expected: "Slice", received: v })
+}
+
+pub fn symbol(v: ValueRef) -> EvalResult<ValueRefT<Symbol>> {
+    v.try_downcast::<Symbol>().ok_or(EvalError::Type { expected: "Symbol", received: v })
+}
+
+pub fn promise(v: ValueRef) -> EvalResult<ValueRefT<Promise>> {
+    v.try_downcast::<Promise>().ok_or(EvalError::Type { expected: "Promise", received: v })
+}
+
+pub fn env(v: ValueRef) -> EvalResult<ValueRefT<Env>> {
+    v.try_downcast::<Env>().ok_or(EvalError::Type { expected: "Env", received: v })
+}
+
+pub fn string(v: ValueRef) -> EvalResult<ValueRefT<String>> {
+    v.try_downcast::<String>().ok_or(EvalError::Type { expected: "String", received: v })
+}
+
+pub fn exports(v: ValueRef) -> EvalResult<ValueRefT<Exports>> {
+    v.try_downcast::<Exports>().ok_or(EvalError::Type { expected: "Exports", received: v })
+}
+
+/// The type of `v` as seen by programs, where all kinds of functions have the type `Fn` (that of
+/// `Closure`).
+pub fn type_of(v: ValueRef) -> ValueRefT<Type> {
+    let types = TypeRegistry::instance();
+    if v.is_instance::<Continuation>() || v.is_instance::<OneShot>() {
+        types.reify::<Closure>()
+    } else {
+        types.type_of(v)
+    }
+}
+
+pub fn typ(v: ValueRef) -> EvalResult<ValueRefT<Type>> {
+    v.try_downcast::<Type>().ok_or(EvalError::Type { expected: "Type", received: v })
+}
+
+pub fn record(v: ValueRef) -> EvalResult<ValueRefT<Record>> {
+    Record::from_value(v).ok_or(EvalError::Type { expected: "Record", received: v })
+}
+
+pub fn record_type(v: ValueRef) -> EvalResult<ValueRefT<Type>> {
+    v.try_downcast::<Type>()
+     .and_then(|typ| typ.record_len().map(|_| typ))
+     .ok_or(EvalError::Type { expected: "record Type", received: v })
+}
+
+pub fn map(v: ValueRef) -> EvalResult<ValueRefT<Map>> {
+    v.try_downcast::<Map>().ok_or(EvalError::Type { expected: "Map", received: v })
+}
+
+pub fn set(v: ValueRef) -> EvalResult<ValueRefT<Set>> {
+    v.try_downcast::<Set>().ok_or(EvalError::Type { expected: "Set", received: v })
+}
+
+pub fn bytes(v: ValueRef) -> EvalResult<ValueRefT<Bytes>> {
+    v.try_downcast::<Bytes>().ok_or(EvalError::Type { expected: "Bytes", received: v })
+}
+
+pub fn buffer(v: ValueRef) -> EvalResult<ValueRefT<ByteBuffer>> {
+    v.try_downcast::<ByteBuffer>().ok_or(EvalError::Type { expected: "ByteBuffer", received: v })
+}
+
+/// An Int that fits in a byte.
+fn byte(v: ValueRef) -> EvalResult<u8> {
+    match int(v)? {
+        n if 0 <= n && n < 256 => Ok(n as u8),
+        _ => Err(EvalError::Type { expected: "byte", received: v })
+    }
+}
+
+pub fn character(v: ValueRef) -> EvalResult<char> {
+    v.try_unbox::<char>().ok_or(EvalError::Type { expected: "Char", received: v })
+}
+
+/// The characters that `__toString` converts `v` to: those of Strings, Chars and Ropes as such and
+/// the printed representation of other values.
+pub fn to_string(v: ValueRef) -> ::std::string::String {
+    if let Some(s) = v.try_downcast::<String>() {
+        s.chars().to_string()
+    } else if let Some(c) = v.try_unbox::<char>() {
+        c.to_string()
+    } else if let Some(rope) = v.try_downcast::<Rope>() {
+        let mut chars = ::std::string::String::new();
+        rope.write_to(&mut chars);
+        chars
+    } else {
+        v.to_string()
+    }
+}
+
+/// The byte offset of the code point at index `i` of `s` (or the length of `s` if `i` is the
+/// number of code points).
+fn byte_offset(s: &str, i: usize) -> usize {
+    s.char_indices().nth(i).map_or(s.len(), |(offset, _)| offset)
+}
+
+/// The fields of `record`, which must all have been initialized.
+fn fields(record: ValueRefT<Record>) -> EvalResult<Vec<ValueRef>> {
+    record.fields().iter().enumerate()
+          .map(|(i, field)| field.ok_or(EvalError::UninitializedField(i)))
+          .collect()
+}
+
+/// Check that the Int `v` is an index into a sequence of length `len`.
+fn index(v: ValueRef, len: usize) -> EvalResult<usize> {
+    let i = int(v)?;
+    if 0 <= i && (i as usize) < len {
+        Ok(i as usize)
+    } else {
+        Err(EvalError::Bounds { index: i, len })
+    }
+}
+
+/// The bounds `start..end` of a subsequence of a sequence of length `len`.
+fn range(start: ValueRef, end: ValueRef, len: usize) -> EvalResult<(usize, usize)> {
+    let (start, end) = (int(start)?, int(end)?);
+    if 0 <= start && start <= end && (end as usize) <= len {
+        Ok((start as usize, end as usize))
+    } else {
+        Err(EvalError::Range { start, end, len })
+    }
+}
+
+fn len(n: usize) -> ValueRef { ValueRefT::from(n as isize).into() }
+
+// ================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use pcws_domain::Allocator;
+    use pcws_domain::object_model::{ValueRef, ValueRefT};
+    use pcws_domain::values::{Tuple, Slice, Symbol, String, Promise, BigInt, Integer, Float,
+                              Bytes};
+    use pcws_syntax::cst::PrimOp;
+
+    use interpret::EvalError;
+    use env::Env;
+    use super::{apply_pure, Overflow, iadd, idiv_rem};
+
+    fn int(n: isize) -> ValueRef { ValueRefT::from(n).into() }
+
+    fn boolean(b: bool) -> ValueRef { ValueRefT::from(b).into() }
+
+    fn float(n: f64) -> ValueRef { ValueRefT::<f64>::checked(n).unwrap().into() }
+
+    fn character(c: char) -> ValueRef { ValueRefT::from(c).into() }
+
+    fn call(op: PrimOp, args: &[ValueRef]) -> Result<ValueRef, EvalError> {
+        ::register_types();
+        apply_pure(&mut *Allocator::instance(), op, args)
+    }
+
+    fn tuple(vals: &[ValueRef]) -> ValueRef { call(PrimOp::Tuple, vals).unwrap() }
+
+    fn string(s: &str) -> ValueRef { String::new(&mut *Allocator::instance(), s).unwrap().into() }
+
+    fn chars(v: ValueRef) -> ::std::string::String {
+        v.try_downcast::<String>().unwrap().chars().to_string()
+    }
+
+    #[test]
+    fn tuple_new() {
+        let t = tuple(&[int(1), int(2)]).try_downcast::<Tuple>().unwrap();
+        assert_eq!(t.vals(), &[int(1), int(2)]);
+    }
+
+    #[test]
+    fn tuple_len() {
+        assert_eq!(call(PrimOp::TupleLen, &[tuple(&[int(1), int(2)])]).unwrap(), int(2));
+        match call(PrimOp::TupleLen, &[int(1)]) {
+            Err(EvalError::Type { expected: "Tuple", .. }) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn tuple_get() {
+        let t = tuple(&[int(1), int(2)]);
+        assert_eq!(call(PrimOp::TupleGet, &[t, int(1)]).unwrap(), int(2));
+        match call(PrimOp::TupleGet, &[t, int(2)]) {
+            Err(EvalError::Bounds { index: 2, len: 2 }) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn tuple_slice() {
+        let s = call(PrimOp::TupleSlice, &[tuple(&[int(1), int(2)])]).unwrap();
+        assert_eq!(s.try_downcast::<Slice>().unwrap().vals(), &[int(1), int(2)]);
+    }
+
+    #[test]
+    fn slice_len() {
+        let s = call(PrimOp::TupleSlice, &[tuple(&[int(1), int(2)])]).unwrap();
+        assert_eq!(call(PrimOp::SliceLen, &[s]).unwrap(), int(2));
+    }
+
+    #[test]
+    fn slice_get() {
+        let s = call(PrimOp::TupleSlice, &[tuple(&[int(1), int(2), int(3)])]).unwrap();
+        let s = call(PrimOp::SliceSubP, &[s, int(1), int(3)]).unwrap();
+        assert_eq!(call(PrimOp::SliceGetP, &[s, int(0)]).unwrap(), int(2));
+        match call(PrimOp::SliceGetP, &[s, int(-1)]) {
+            Err(EvalError::Bounds { index: -1, len: 2 }) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn slice_sub() {
+        let s = call(PrimOp::TupleSlice, &[tuple(&[int(1), int(2), int(3)])]).unwrap();
+        let sub = call(PrimOp::SliceSubP, &[s, int(1), int(3)]).unwrap();
+        assert_eq!(sub.try_downcast::<Slice>().unwrap().vals(), &[int(2), int(3)]);
+        let empty = call(PrimOp::SliceSubP, &[sub, int(2), int(2)]).unwrap();
+        assert!(empty.try_downcast::<Slice>().unwrap().is_empty());
+        match call(PrimOp::SliceSubP, &[s, int(2), int(1)]) {
+            Err(EvalError::Range { start: 2, end: 1, len: 3 }) => {},
+            res => panic!("{:?}", res)
+        }
+        assert!(call(PrimOp::SliceSubP, &[s, int(0), int(4)]).is_err());
+    }
+
+    #[test]
+    fn iadd() {
+        assert_eq!(call(PrimOp::IAdd, &[int(2), int(-5)]).unwrap(), int(-3));
+        match call(PrimOp::IAdd, &[int(2)]) {
+            Err(EvalError::Argc { expected: 2, received: 1 }) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn iadd_overflow() {
+        let max = ValueRefT::<isize>::MAX;
+        let big = call(PrimOp::IAdd, &[int(max), int(1)]).unwrap();
+        assert!(big.try_downcast::<BigInt>().is_some());
+        assert_eq!(Integer::from_value(big), Some(Integer::from(max + 1)));
+        assert_eq!(call(PrimOp::ISub, &[big, int(1)]).unwrap(), int(max));
+        assert_eq!(iadd(max, 1), Err(Overflow::Wrapped(ValueRefT::<isize>::MIN)));
+    }
+
+    #[test]
+    fn bigint_arithmetic() {
+        let big = call(PrimOp::IMul, &[int(1 << 40), int(1 << 40)]).unwrap();
+        assert_eq!(Integer::from_value(big), Integer::parse("1208925819614629174706176"));
+        assert_eq!(call(PrimOp::IDiv, &[big, int(1 << 40)]).unwrap(), int(1 << 40));
+        assert_eq!(call(PrimOp::ILt, &[int(1), big]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::IGe, &[int(1), big]).unwrap(), boolean(false));
+    }
+
+    #[test]
+    fn isub() {
+        assert_eq!(call(PrimOp::ISub, &[int(2), int(5)]).unwrap(), int(-3));
+        let min = ValueRefT::<isize>::MIN;
+        let big = call(PrimOp::ISub, &[int(min), int(1)]).unwrap();
+        assert!(big.try_downcast::<BigInt>().is_some());
+        assert_eq!(super::isub(min, 1), Err(Overflow::Wrapped(ValueRefT::<isize>::MAX)));
+    }
+
+    #[test]
+    fn imul() {
+        assert_eq!(call(PrimOp::IMul, &[int(-4), int(5)]).unwrap(), int(-20));
+        let max = ValueRefT::<isize>::MAX;
+        let big = call(PrimOp::IMul, &[int(max), int(2)]).unwrap();
+        assert!(big.try_downcast::<BigInt>().is_some());
+        assert_eq!(super::imul(1 << 40, 1 << 40), Err(Overflow::Wide { hi: 1 << 20, lo: 0 }));
+        assert_eq!(super::imul(max, 2), Err(Overflow::Wide { hi: 1, lo: (1 << 60) - 2 }));
+        assert_eq!(super::imul(max, -2), Err(Overflow::Wide { hi: -2, lo: 2 }));
+    }
+
+    #[test]
+    fn idiv() {
+        assert_eq!(call(PrimOp::IDiv, &[int(-7), int(2)]).unwrap(), int(-3));
+        match call(PrimOp::IDiv, &[int(1), int(0)]) {
+            Err(EvalError::DivByZero) => {},
+            res => panic!("{:?}", res)
+        }
+        let quot = call(PrimOp::IDiv, &[int(ValueRefT::<isize>::MIN), int(-1)]).unwrap();
+        assert_eq!(Integer::from_value(quot), Some(Integer::from(ValueRefT::<isize>::MAX + 1)));
+        assert_eq!(idiv_rem(-7, 2).unwrap(), Ok((-3, -1)));
+    }
+
+    #[test]
+    fn icmp() {
+        assert_eq!(call(PrimOp::ILt, &[int(1), int(2)]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::ILe, &[int(2), int(2)]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::IGt, &[int(1), int(2)]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::IGe, &[int(1), int(2)]).unwrap(), boolean(false));
+    }
+
+    #[test]
+    fn ibitwise() {
+        assert_eq!(call(PrimOp::IAnd, &[int(0b1100), int(0b1010)]).unwrap(), int(0b1000));
+        assert_eq!(call(PrimOp::IOr, &[int(0b1100), int(0b1010)]).unwrap(), int(0b1110));
+        assert_eq!(call(PrimOp::IXor, &[int(0b1100), int(0b1010)]).unwrap(), int(0b0110));
+        assert_eq!(call(PrimOp::INot, &[int(0)]).unwrap(), int(-1));
+        assert_eq!(call(PrimOp::IPopCount, &[int(0b1011)]).unwrap(), int(3));
+        assert_eq!(call(PrimOp::IPopCount, &[int(-1)]).unwrap(), int(61));
+    }
+
+    #[test]
+    fn ishift() {
+        assert_eq!(call(PrimOp::IShl, &[int(1), int(5)]).unwrap(), int(32));
+        assert!(call(PrimOp::IShl, &[int(1), int(60)]).is_err());
+        assert!(call(PrimOp::IShl, &[int(1), int(-1)]).is_err());
+        assert_eq!(call(PrimOp::IShr, &[int(-8), int(2)]).unwrap(), int(-2));
+        assert_eq!(call(PrimOp::IShr, &[int(-8), int(100)]).unwrap(), int(-1));
+        assert_eq!(call(PrimOp::IShrL, &[int(-1), int(57)]).unwrap(), int(0b1111));
+    }
+
+    #[test]
+    fn farith() {
+        let tenth = Float::new(&mut *Allocator::instance(), 0.1).unwrap();
+        assert!(tenth.try_downcast::<Float>().is_some());
+        let sum = call(PrimOp::FAdd, &[tenth, tenth]).unwrap();
+        assert_eq!(Float::from_value(sum), Some(0.2));
+        let half = call(PrimOp::FDiv, &[float(1.0), float(2.0)]).unwrap();
+        assert_eq!(half, float(0.5));
+        assert_eq!(call(PrimOp::FSub, &[float(1.0), float(2.0)]).unwrap(), float(-1.0));
+        assert_eq!(call(PrimOp::FMul, &[float(1.5), float(2.0)]).unwrap(), float(3.0));
+        assert!(call(PrimOp::FAdd, &[float(1.0), int(2)]).is_err());
+    }
+
+    #[test]
+    fn fcmp() {
+        let nan = float(::std::f64::NAN);
+        assert_eq!(call(PrimOp::FEq, &[nan, nan]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::FLt, &[float(1.0), float(2.0)]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::FLe, &[float(2.0), float(2.0)]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::FGt, &[float(1.0), float(2.0)]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::FGe, &[float(1.0), nan]).unwrap(), boolean(false));
+    }
+
+    #[test]
+    fn float_conversions() {
+        assert_eq!(call(PrimOp::IToF, &[int(-3)]).unwrap(), float(-3.0));
+        assert_eq!(call(PrimOp::FToI, &[float(-3.75)]).unwrap(), int(-3));
+        let big = call(PrimOp::FToI, &[float(-2f64.powi(70))]).unwrap();
+        assert!(big.try_downcast::<BigInt>().is_some());
+        assert_eq!(big.to_string(), "-1180591620717411303424");
+        assert!(call(PrimOp::FToI, &[float(::std::f64::INFINITY)]).is_err());
+    }
+
+    #[test]
+    fn float_printing() {
+        for &n in &[0.1, 1.0, 1e300, -2.5e-8] {
+            let v = Float::new(&mut *Allocator::instance(), n).unwrap();
+            assert_eq!(v.to_string().parse::<f64>(), Ok(n));
+        }
+        assert_eq!(float(1.0).to_string(), "1.0");
+    }
+
+    #[test]
+    fn symbol_fresh() {
+        let name = Symbol::new(&mut *Allocator::instance(), "foo").unwrap();
+        let sym = call(PrimOp::SymbolFresh, &[name.into()]).unwrap();
+        assert!(sym != name.into());
+        assert_eq!(sym.try_downcast::<Symbol>().unwrap().chars(), "foo");
+    }
+
+    #[test]
+    fn promise() {
+        let p = call(PrimOp::Promise, &[]).unwrap();
+        assert!(p.try_downcast::<Promise>().is_some());
+        assert_eq!(p.force(), None);
+    }
+
+    #[test]
+    fn redirect() {
+        let p = call(PrimOp::Promise, &[]).unwrap();
+        assert_eq!(call(PrimOp::Redirect, &[p, int(5)]).unwrap(), int(5));
+        assert_eq!(p.force(), Some(int(5)));
+        match call(PrimOp::Redirect, &[p, int(6)]) {
+            Err(EvalError::Reinit(None)) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn eq() {
+        let t = tuple(&[]);
+        assert_eq!(call(PrimOp::Eq, &[t, t]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::Eq, &[t, tuple(&[])]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::Eq, &[int(3), int(3)]).unwrap(), boolean(true));
+    }
+
+    #[test]
+    fn equal() {
+        let big = |n: isize| call(PrimOp::IMul, &[int(n), int(1 << 40)]).unwrap();
+        let rope = |pieces: &[ValueRef]| call(PrimOp::Rope, pieces).unwrap();
+        let boxed_nan = ::std::f64::from_bits(::std::f64::NAN.to_bits() | 1);
+        let pairs = [(tuple(&[int(1), string("foo")]), tuple(&[int(1), string("foo")])),
+                     (big(1 << 40), big(1 << 40)),
+                     (tuple(&[]), tuple(&[])),
+                     (rope(&[string("fo"), character('o')]), string("foo")),
+                     (rope(&[character('f'), rope(&[string("oo")])]),
+                      rope(&[string("f"), string("oo")])),
+                     (rope(&[]), string("")),
+                     (Float::new(&mut *Allocator::instance(), boxed_nan).unwrap(),
+                      float(::std::f64::NAN))];
+        for &(a, b) in &pairs {
+            assert!(a != b);
+            assert_eq!(call(PrimOp::Equal, &[a, b]).unwrap(), boolean(true));
+            assert_eq!(call(PrimOp::Hash, &[a]).unwrap(), call(PrimOp::Hash, &[b]).unwrap());
+        }
+        assert_eq!(call(PrimOp::Equal, &[tuple(&[int(1), string("foo")]),
+                                         tuple(&[int(1), string("bar")])]).unwrap(),
+                   boolean(false));
+        assert_eq!(call(PrimOp::Equal, &[tuple(&[int(1)]), tuple(&[int(1), int(2)])]).unwrap(),
+                   boolean(false));
+        assert_eq!(call(PrimOp::Equal, &[int(1), float(1.0)]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::Equal, &[rope(&[string("fo"), character('x')]), string("foo")])
+                       .unwrap(),
+                   boolean(false));
+
+        let name = Symbol::new(&mut *Allocator::instance(), "Box").unwrap();
+        let box_type = call(PrimOp::NewRecordType, &[name.into(), int(1)]).unwrap();
+        let cyclic = || {
+            let b = call(PrimOp::BeginRecord, &[box_type, int(1)]).unwrap();
+            call(PrimOp::RecordInit, &[b, int(0), b]).unwrap()
+        };
+        let (a, b) = (cyclic(), cyclic());
+        assert_eq!(call(PrimOp::Equal, &[a, b]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::Hash, &[a]).unwrap(), call(PrimOp::Hash, &[b]).unwrap());
+    }
+
+    #[test]
+    fn typ() {
+        let t1 = call(PrimOp::Type, &[tuple(&[])]).unwrap();
+        let t2 = call(PrimOp::Type, &[tuple(&[int(1)])]).unwrap();
+        assert_eq!(t1, t2);
+
+        let int_type = call(PrimOp::Type, &[int(1)]).unwrap();
+        let big = call(PrimOp::IMul, &[int(1 << 40), int(1 << 40)]).unwrap();
+        assert_eq!(call(PrimOp::Type, &[big]).unwrap(), int_type);
+        assert!(call(PrimOp::Type, &[float(1.0)]).unwrap() != int_type);
+        assert_eq!(int_type.to_string(), "Int");
+        assert_eq!(call(PrimOp::Type, &[int_type]).unwrap().to_string(), "Type");
+        assert_eq!(call(PrimOp::Type, &[boolean(true)]).unwrap().to_string(), "Bool");
+    }
+
+    #[test]
+    fn type_names() {
+        let tuple_type = call(PrimOp::BuiltinType, &[string("Tuple")]).unwrap();
+        assert_eq!(call(PrimOp::Type, &[tuple(&[])]).unwrap(), tuple_type);
+        let type_name = call(PrimOp::TypeName, &[tuple_type]).unwrap();
+        assert_eq!(type_name.try_downcast::<String>().unwrap().chars(), "Tuple");
+        assert!(call(PrimOp::BuiltinType, &[string("Nonesuch")]).is_err());
+        assert!(call(PrimOp::TypeName, &[int(1)]).is_err());
+    }
+
+    #[test]
+    fn records() {
+        let name = Symbol::new(&mut *Allocator::instance(), "Pair").unwrap();
+        let pair = call(PrimOp::NewRecordType, &[name.into(), int(2)]).unwrap();
+        assert!(pair != call(PrimOp::NewRecordType, &[name.into(), int(2)]).unwrap());
+        assert!(call(PrimOp::BeginRecord, &[pair, int(3)]).is_err());
+
+        let p = call(PrimOp::BeginRecord, &[pair, int(2)]).unwrap();
+        call(PrimOp::RecordInit, &[p, int(0), int(1)]).unwrap();
+        match call(PrimOp::EndRecord, &[p]) {
+            Err(EvalError::UninitializedField(1)) => {},
+            res => panic!("{:?}", res)
+        }
+        call(PrimOp::RecordInit, &[p, int(1), int(2)]).unwrap();
+        match call(PrimOp::RecordInit, &[p, int(1), int(3)]) {
+            Err(EvalError::ReinitField(1)) => {},
+            res => panic!("{:?}", res)
+        }
+        assert_eq!(call(PrimOp::EndRecord, &[p]).unwrap(), p);
+
+        assert_eq!(call(PrimOp::Type, &[p]).unwrap(), pair);
+        assert_eq!(p.to_string(), "Pair(1, 2)");
+        let fields = call(PrimOp::RecordSlice, &[p]).unwrap();
+        assert_eq!(fields.try_downcast::<Slice>().unwrap().vals(), &[int(1), int(2)]);
+        assert!(call(PrimOp::RecordSlice, &[tuple(&[])]).is_err());
+    }
+
+    #[test]
+    fn maps() {
+        let m = call(PrimOp::Map, &[int(1), int(2), tuple(&[int(3)]), int(4), int(1), int(5)]);
+        let m = m.unwrap();
+        assert_eq!(call(PrimOp::MapLen, &[m]).unwrap(), int(2));
+        assert_eq!(call(PrimOp::MapGet, &[m, int(1)]).unwrap(), int(5));
+        assert_eq!(call(PrimOp::MapGet, &[m, tuple(&[int(3)])]).unwrap(), int(4));
+        match call(PrimOp::MapGet, &[m, int(3)]) {
+            Err(EvalError::NoKey(key)) => assert_eq!(key, int(3)),
+            res => panic!("{:?}", res)
+        }
+        assert!(call(PrimOp::Map, &[int(1)]).is_err());
+        assert!(call(PrimOp::MapLen, &[tuple(&[])]).is_err());
+
+        let mut big = call(PrimOp::Map, &[]).unwrap();
+        for i in 0..200 {
+            big = call(PrimOp::MapInsert, &[big, int(i), int(i * i)]).unwrap();
+        }
+        assert_eq!(call(PrimOp::MapLen, &[big]).unwrap(), int(200));
+        assert!((0..200).all(|i| call(PrimOp::MapGet, &[big, int(i)]).unwrap() == int(i * i)));
+
+        let mut small = big;
+        for i in 0..198 {
+            small = call(PrimOp::MapRemove, &[small, int(i)]).unwrap();
+        }
+        assert_eq!(call(PrimOp::MapRemove, &[small, int(0)]).unwrap(), small);
+        assert_eq!(call(PrimOp::MapLen, &[small]).unwrap(), int(2));
+        assert_eq!(call(PrimOp::MapHas, &[small, int(0)]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::MapHas, &[big, int(0)]).unwrap(), boolean(true));
+        // The trie only depends on the keys, not on the order they were inserted and removed in:
+        let fresh = call(PrimOp::Map, &[int(199), int(39601), int(198), int(39204)]).unwrap();
+        assert_eq!(small.to_string(), fresh.to_string());
+        assert_eq!(call(PrimOp::Map, &[]).unwrap().to_string(), "{->}");
+    }
+
+    #[test]
+    fn sets() {
+        let s = call(PrimOp::Set, &[int(1), tuple(&[]), int(1)]).unwrap();
+        assert_eq!(call(PrimOp::SetLen, &[s]).unwrap(), int(2));
+        assert_eq!(call(PrimOp::SetHas, &[s, tuple(&[])]).unwrap(), boolean(true));
+        assert_eq!(call(PrimOp::SetHas, &[s, int(2)]).unwrap(), boolean(false));
+        let t = call(PrimOp::SetInsert, &[s, int(2)]).unwrap();
+        assert_eq!(call(PrimOp::SetLen, &[t]).unwrap(), int(3));
+        let u = call(PrimOp::SetRemove, &[t, int(1)]).unwrap();
+        assert_eq!(call(PrimOp::SetHas, &[u, int(1)]).unwrap(), boolean(false));
+        assert_eq!(call(PrimOp::SetHas, &[s, int(1)]).unwrap(), boolean(true));
+        assert!(call(PrimOp::SetHas, &[int(1), int(1)]).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let s = string("häst");
+        assert_eq!(call(PrimOp::StringLen, &[s]).unwrap(), int(4));
+        assert_eq!(call(PrimOp::StringGet, &[s, int(1)]).unwrap(), character('ä'));
+        assert!(call(PrimOp::StringGet, &[s, int(4)]).is_err());
+        assert_eq!(chars(call(PrimOp::StringSub, &[s, int(1), int(4)]).unwrap()), "äst");
+        assert_eq!(chars(call(PrimOp::StringSub, &[s, int(4), int(4)]).unwrap()), "");
+        match call(PrimOp::StringSub, &[s, int(2), int(1)]) {
+            Err(EvalError::Range { start: 2, end: 1, len: 4 }) => {},
+            res => panic!("{:?}", res)
+        }
+        assert_eq!(chars(call(PrimOp::StringConcat, &[s, string(" & "), s]).unwrap()),
+                   "häst & häst");
+        assert_eq!(call(PrimOp::StringChars, &[s]).unwrap().to_string(), "(h, ä, s, t)");
+
+        assert_eq!(call(PrimOp::StringCmp, &[string("ab"), string("b")]).unwrap(), int(-1));
+        assert_eq!(call(PrimOp::StringCmp, &[string("z"), string("ä")]).unwrap(), int(-1));
+        assert_eq!(call(PrimOp::StringCmp, &[s, string("häst")]).unwrap(), int(0));
+        assert_eq!(call(PrimOp::StringCmp, &[string("b"), string("ab")]).unwrap(), int(1));
+
+        let sym = call(PrimOp::StringToSymbol, &[s]).unwrap();
+        assert_eq!(sym, Symbol::new(&mut *Allocator::instance(), "häst").unwrap().into());
+        assert_eq!(chars(call(PrimOp::SymbolToString, &[sym]).unwrap()), "häst");
+
+        assert_eq!(call(PrimOp::StringToInt, &[string("-42")]).unwrap(), int(-42));
+        let big = call(PrimOp::StringToInt, &[string("18446744073709551616")]).unwrap();
+        assert!(big.try_downcast::<BigInt>().is_some());
+        assert!(call(PrimOp::StringToInt, &[string("4x")]).is_err());
+        assert_eq!(call(PrimOp::StringToFloat, &[string("2.5")]).unwrap(), float(2.5));
+        assert!(call(PrimOp::StringToFloat, &[string("")]).is_err());
+
+        assert_eq!(call(PrimOp::CharToInt, &[character('ä')]).unwrap(), int(0xe4));
+        assert_eq!(call(PrimOp::IntToChar, &[int(0x1f600)]).unwrap(), character('\u{1f600}'));
+        assert!(call(PrimOp::IntToChar, &[int(0xd800)]).is_err());
+        assert!(call(PrimOp::IntToChar, &[int(-1)]).is_err());
+    }
+
+    #[test]
+    fn ropes() {
+        let mut rope = call(PrimOp::Rope, &[]).unwrap();
+        for i in 0..1000 {
+            let piece = if i % 2 == 0 { string("ab") } else { character('c') };
+            rope = call(PrimOp::Rope, &[rope, piece]).unwrap();
+        }
+        let s = call(PrimOp::ToString, &[rope]).unwrap();
+        assert_eq!(chars(s), "abc".repeat(500));
+        assert!(call(PrimOp::Rope, &[string("a"), int(1)]).is_err());
+
+        assert_eq!(call(PrimOp::ToString, &[s]).unwrap(), s);
+        assert_eq!(chars(call(PrimOp::ToString, &[character('x')]).unwrap()), "x");
+        assert_eq!(chars(call(PrimOp::ToString, &[tuple(&[int(1), string("a")])]).unwrap()),
+                   "(1, \"a\")");
+        assert_eq!(chars(call(PrimOp::ToString, &[float(1.0)]).unwrap()), "1.0");
+    }
+
+    #[test]
+    fn bytes() {
+        let b = call(PrimOp::Bytes, &[int(3)]).unwrap();
+        assert_eq!(call(PrimOp::BytesSet, &[b, int(1), int(255)]).unwrap(), b);
+        assert_eq!(call(PrimOp::BytesGet, &[b, int(1)]).unwrap(), int(255));
+        assert_eq!(b.to_string(), "#<Bytes 00ff00>");
+        assert!(call(PrimOp::BytesSet, &[b, int(0), int(256)]).is_err());
+        assert!(call(PrimOp::BytesGet, &[b, int(3)]).is_err());
+        assert!(call(PrimOp::Bytes, &[int(-1)]).is_err());
+        let sub = call(PrimOp::BytesSub, &[b, int(1), int(3)]).unwrap();
+        assert_eq!(call(PrimOp::BytesLen, &[sub]).unwrap(), int(2));
+        match call(PrimOp::BytesSub, &[b, int(3), int(1)]) {
+            Err(EvalError::Range { start: 3, end: 1, len: 3 }) => {},
+            res => panic!("{:?}", res)
+        }
+        call(PrimOp::BytesSet, &[sub, int(0), int(7)]).unwrap();
+        assert_eq!(call(PrimOp::BytesGet, &[b, int(1)]).unwrap(), int(255));
+
+        let utf8 = call(PrimOp::Utf8Encode, &[string("hä")]).unwrap();
+        assert_eq!(utf8.to_string(), "#<Bytes 68c3a4>");
+        assert_eq!(chars(call(PrimOp::Utf8Decode, &[utf8]).unwrap()), "hä");
+        let cut = call(PrimOp::BytesSub, &[utf8, int(0), int(2)]).unwrap();
+        match call(PrimOp::Utf8Decode, &[cut]) {
+            Err(EvalError::InvalidUtf8(1)) => {},
+            res => panic!("{:?}", res)
+        }
+    }
+
+    #[test]
+    fn byte_buffers() {
+        let buf = call(PrimOp::ByteBuffer, &[]).unwrap();
+        for i in 0..20 {
+            assert_eq!(call(PrimOp::BufferPush, &[buf, int(i)]).unwrap(), buf);
+        }
+        call(PrimOp::BufferAppend, &[buf, string("ä")]).unwrap();
+        let b: ValueRef = Bytes::new(&mut *Allocator::instance(), &[1, 2, 3]).unwrap().into();
+        call(PrimOp::BufferAppend, &[buf, b]).unwrap();
+        assert!(call(PrimOp::BufferPush, &[buf, int(-1)]).is_err());
+        assert!(call(PrimOp::BufferAppend, &[buf, int(1)]).is_err());
+        assert_eq!(call(PrimOp::BufferLen, &[buf]).unwrap(), int(25));
+
+        let contents = call(PrimOp::BufferBytes, &[buf]).unwrap();
+        assert_eq!(call(PrimOp::BytesLen, &[contents]).unwrap(), int(25));
+        assert_eq!(call(PrimOp::BytesGet, &[contents, int(19)]).unwrap(), int(19));
+        assert_eq!(call(PrimOp::BytesGet, &[contents, int(21)]).unwrap(), int(0xa4));
+        assert_eq!(call(PrimOp::BytesGet, &[contents, int(24)]).unwrap(), int(3));
+    }
+
+    #[test]
+    fn denv_empty() {
+        let denv = call(PrimOp::DenvEmpty, &[]).unwrap();
+        let name = Symbol::new(&mut *Allocator::instance(), "foo").unwrap();
+        assert!(denv.try_downcast::<Env>().unwrap().get(name).is_err());
+    }
+
+    #[test]
+    fn assert() {
+        assert_eq!(call(PrimOp::AssertP, &[boolean(true)]).unwrap(), boolean(true));
+        match call(PrimOp::AssertP, &[boolean(false)]) {
+            Err(EvalError::Assertion) => {},
+            res => panic!("{:?}", res)
+        }
+        assert!(call(PrimOp::AssertP, &[int(1)]).is_err());
+    }
+}
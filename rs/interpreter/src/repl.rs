@@ -0,0 +1,241 @@
+use std::mem::transmute;
+use std::iter;
+use std::borrow::Cow;
+use std::fs::File;
+use std::str::FromStr;
+use std::io::{self, Read, BufRead, Write};
+
+use pcws_domain::Allocator;
+use pcws_domain::object_model::{ValueRef, ValueRefT};
+use pcws_domain::values::{Tuple, Symbol};
+use pcws_syntax::open_brackets;
+use pcws_syntax::cst::{Expr, PrimOp, CstFactory, Pos};
+use ast::Block;
+use env::Env;
+use inject::Inject;
+use bootstrap;
+use interpret::{interpret, EvalError, RuntimeError};
+use module::Modules;
+
+// ================================================================================================
+
+const PROMPT: &str = "pcws> ";
+const CONTINUATION_PROMPT: &str = "....> ";
+
+const HELP: &str = "\
+Enter an expression or a sequence of statements to evaluate it. Definitions stay in scope for
+the rest of the session; end the input with `;` to evaluate only the definitions. Input
+continues on the next line while brackets are left open.
+
+:ast <expr>    print the abstract syntax tree of <expr>
+:heap          print heap statistics
+:load <file>   evaluate the program in <file> in the session
+:help          print this message
+:quit          end the session (as does end of input)";
+
+/// Read-eval-print loop session
+///
+/// The top-level variables of each input are declared in an environment whose parent holds
+/// those of the previous inputs, so definitions persist (and can be shadowed) across inputs.
+pub struct Repl {
+    lenv: Option<ValueRefT<Env>>,
+    denv: ValueRefT<Env>,
+    modules: Modules
+}
+
+impl Repl {
+    /// Start a session that has `apply` and `unapply` (see `bootstrap::with_apply`) and, if
+    /// `prelude` is true, the exports of the prelude defined and requires modules from `modules`.
+    pub fn new(mut modules: Modules, prelude: bool) -> Result<Repl, RuntimeError> {
+        let denv = bootstrap::host_denv(&mut *Allocator::instance(), &[]).ok_or(EvalError::OOM)?;
+        let lenv = if prelude { bootstrap::prelude(denv, &mut modules)? } else { None };
+        let mut repl = Repl { lenv, denv, modules };
+        let unit = CstFactory::new(Pos::default()).primcall(PrimOp::Tuple, vec![]);
+        repl.eval(bootstrap::with_apply(unit))?;
+        Ok(repl)
+    }
+
+    /// Evaluate `program` in the session, keeping its definitions if it succeeds.
+    pub fn eval(&mut self, program: Expr) -> Result<ValueRef, RuntimeError> {
+        let (ast, lenv, denv) = {
+            let heap = &mut *Allocator::instance();
+            let ast = program.inject(heap).ok_or(EvalError::OOM)?;
+            toplevel(heap, ast, self.lenv, self.denv).ok_or(EvalError::OOM)?
+        };
+        let value = interpret(ast, lenv, denv, &mut self.modules)?;
+        self.lenv = lenv;
+        self.denv = denv;
+        Ok(value)
+    }
+
+    /// Read inputs from `input` and write their results to `output` until the end of `input` or
+    /// `:quit`.
+    pub fn run<R: BufRead, W: Write>(&mut self, mut input: R, output: &mut W) -> io::Result<()> {
+        while let Some(src) = read_input(&mut input, output)? {
+            let src = src.trim();
+            if src.starts_with(':') {
+                if !self.command(src, output)? {
+                    break;
+                }
+            } else if !src.is_empty() {
+                self.eval_src(src, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute the meta-command `line`, returning `false` if the session should end.
+    fn command<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, "")
+        };
+        match command {
+            ":ast" => match Expr::from_str(arg) {
+                Ok(program) => match program.inject(&mut *Allocator::instance()) {
+                    Some(ast) => writeln!(output, "{}", ast)?,
+                    None => writeln!(output, "error: {}", EvalError::OOM)?
+                },
+                Err(err) => writeln!(output, "parse error: {:?}", err)?
+            },
+            ":heap" => {
+                let stats = Allocator::instance().stats();
+                writeln!(output, "{} KiB in {} blocks and {} large objects, {} collections",
+                         stats.heap_bytes / 1024, stats.blocks, stats.large_objects,
+                         stats.collections)?;
+            },
+            ":load" => {
+                let mut src = String::new();
+                match File::open(arg).and_then(|mut file| file.read_to_string(&mut src)) {
+                    Ok(_) => self.eval_src(&src, output)?,
+                    Err(err) => writeln!(output, "cannot read {}: {}", arg, err)?
+                }
+            },
+            ":help" => writeln!(output, "{}", HELP)?,
+            ":quit" => return Ok(false),
+            _ => writeln!(output, "unknown command {} (try :help)", command)?
+        }
+        Ok(true)
+    }
+
+    /// Parse and evaluate `src`, writing the result or error to `output`. If `src` ends with `;`,
+    /// the empty tuple is used as its final expression.
+    fn eval_src<W: Write>(&mut self, src: &str, output: &mut W) -> io::Result<()> {
+        let src = if src.trim_right().ends_with(';') {
+            Cow::Owned(format!("{} __tuple", src))
+        } else {
+            Cow::Borrowed(src)
+        };
+        match Expr::from_str(&src) {
+            Ok(program) => match self.eval(program) {
+                Ok(value) => writeln!(output, "{}", value),
+                Err(err) => writeln!(output, "{}", err)
+            },
+            Err(err) => writeln!(output, "parse error: {:?}", err)
+        }
+    }
+}
+
+/// Read lines from `input` until they make up a meta-command or leave no brackets open. Returns
+/// `None` at the end of `input`.
+fn read_input<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<Option<String>> {
+    let mut src = String::new();
+    loop {
+        write!(output, "{}", if src.is_empty() { PROMPT } else { CONTINUATION_PROMPT })?;
+        output.flush()?;
+        if input.read_line(&mut src)? == 0 {
+            return Ok(if src.is_empty() { None } else { Some(src) });
+        }
+        if src.trim_left().starts_with(':') || open_brackets(&src) <= 0 {
+            return Ok(Some(src));
+        }
+    }
+}
+
+/// Prepare the injected input `ast` for evaluation with `lenv` and `denv`.
+///
+/// If `ast` is a block, its variables are declared in new child environments of `lenv` and `denv`
+/// instead of the block itself so that they outlive it. Returns the AST to evaluate and the
+/// environments to evaluate it in.
+fn toplevel(heap: &mut Allocator, ast: ValueRef, lenv: Option<ValueRefT<Env>>,
+            denv: ValueRefT<Env>) -> Option<(ValueRef, Option<ValueRefT<Env>>, ValueRefT<Env>)>
+{
+    if let Some(block) = ast.try_downcast::<Block>() {
+        let lex_binders: &[ValueRefT<Symbol>] = unsafe { transmute(block.lex_binders().vals()) };
+        let dyn_binders: &[ValueRefT<Symbol>] = unsafe { transmute(block.dyn_binders().vals()) };
+        let lenv = if lex_binders.is_empty() {
+            lenv
+        } else {
+            Some(Env::block(heap, lenv, lex_binders)?)
+        };
+        let denv = if dyn_binders.is_empty() {
+            denv
+        } else {
+            Env::block(heap, Some(denv), dyn_binders)?
+        };
+        let no_binders = Tuple::new(heap, 0, iter::empty())?;
+        let block = Block::new(heap, block.pos(), no_binders, no_binders,
+                               block.stmts(), block.expr())?;
+        Some((block.into(), lenv, denv))
+    } else {
+        Some((ast, lenv, denv))
+    }
+}
+
+// ================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+    use module::Modules;
+
+    fn session(input: &str) -> Vec<String> {
+        ::register_types();
+        session_in(Repl::new(Modules::default(), false).unwrap(), input)
+    }
+
+    fn session_in(mut repl: Repl, input: &str) -> Vec<String> {
+        let mut output = Vec::new();
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+            .split(super::PROMPT)
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn persistent_definitions() {
+        assert_eq!(session("x = 2; y = 3; __iAdd x y\nf = {n => __iMul n x};\nf y\n"),
+                   &["5", "()", "6"]);
+    }
+
+    #[test]
+    fn multiline_input() {
+        assert_eq!(session("f = {a b =>\n  __iAdd a b}; f (__iMul 2\n3) 4\n:quit\n5\n"),
+                   &["....> ....> 10"]);
+    }
+
+    #[test]
+    fn failed_input() {
+        let outputs = session("x = 1;\nx = __iAdd 1 (__tuple); y = 2;\nx\n:load /nonexistent\n");
+        assert!(outputs[1].starts_with("error: expected"));
+        assert_eq!(outputs[2], "1");
+        assert!(outputs[3].starts_with("cannot read /nonexistent"));
+    }
+
+    #[test]
+    fn prelude() {
+        ::register_types();
+        let repl = Repl::new(Modules::default(), true).unwrap();
+        assert_eq!(session_in(repl, "xs = map {x => __iMul x x} (Cons 2 (Cons 3 Nil));\n\
+                                     foldLeft {acc x => __iAdd acc x} 0 xs\n\
+                                     t = set (table (1, 2, 3, 4)) 5 6; \
+                                     __tuple (count t) (get t 3) (get t 7)\n\
+                                     __tuple ((:) xs (typeOf (Cons 1 Nil))) ((:) Nil Any) \
+                                             (foldEntriesLeft {a k v => __iAdd a (__iMul k v)} \
+                                                              0 t)\n\
+                                     prompt 0 [__iAdd 1 (abort 0 4)] {k v => force [v]}\n"),
+                   &["()", "13", "(3, (4), ())", "(true, false, 44)", "4"]);
+    }
+}
@@ -21,6 +21,13 @@ impl FromStr for Expr {
     }
 }
 
+impl Expr {
+    /// Parse `source`, the contents of `file` (which is recorded in the positions of the CST).
+    pub fn parse(source: &str, file: &str) -> Result<Expr, ParseError> {
+        parser::program(&mut Lexer::with_file(source, file), &RefCell::new(IdFactory::new()))
+    }
+}
+
 // ================================================================================================
 
 pub type DefRef = Rc<RefCell<Def>>;
@@ -53,20 +60,117 @@ pub enum PrimOp {
     SliceSubP,
 
     IAdd,
+    ISub,
+    IMul,
+    IDiv,
+    IAddS,
+    ISubS,
+    IMulS,
+    IDivRem,
+
+    ILt,
+    ILe,
+    IGt,
+    IGe,
+
+    IAnd,
+    IOr,
+    IXor,
+    INot,
+    IShl,
+    IShr,
+    IShrL,
+    IPopCount,
+
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FEq,
+    FLt,
+    FLe,
+    FGt,
+    FGe,
+    IToF,
+    FToI,
 
     SymbolFresh,
 
+    StringLen,
+    StringGet,
+    StringSub,
+    StringConcat,
+    StringCmp,
+    StringChars,
+    StringToSymbol,
+    SymbolToString,
+    StringToInt,
+    StringToFloat,
+    CharToInt,
+    IntToChar,
+    Rope,
+    ToString,
+
+    Bytes,
+    BytesLen,
+    BytesGet,
+    BytesSet,
+    BytesSub,
+    Utf8Encode,
+    Utf8Decode,
+    ByteBuffer,
+    BufferLen,
+    BufferPush,
+    BufferAppend,
+    BufferBytes,
+
     Promise,
     Redirect,
 
     Eq,
+    Equal,
+    Hash,
     Type,
+    TypeName,
+    BuiltinType,
+
+    NewRecordType,
+    BeginRecord,
+    RecordInit,
+    EndRecord,
+    RecordSlice,
+
+    Map,
+    MapLen,
+    MapGet,
+    MapHas,
+    MapInsert,
+    MapRemove,
+    MapFold,
+    Set,
+    SetLen,
+    SetHas,
+    SetInsert,
+    SetRemove,
+    SetFold,
 
     DenvEmpty,
     Denv,
     DenvGet,
 
     Prompt,
+    Abort,
+    AbortOnce,
+
+    Raise,
+    Try,
+
+    Require,
+    Export,
+    Member,
+
+    /// Force a garbage collection (only supported by test builds of the interpreter).
+    Collect,
 
     AssertP
 }
@@ -82,6 +186,145 @@ impl Display for PrimOp {
     }
 }
 
+#[derive(Debug)]
+pub struct UnknownPrimOp;
+
+impl FromStr for PrimOp {
+    type Err = UnknownPrimOp;
+
+    /// Parse the name that `Display` produces, e.g. `__tupleLen`.
+    fn from_str(name: &str) -> Result<PrimOp, UnknownPrimOp> {
+        use self::PrimOp::*;
+
+        Ok(match name {
+            "__tuple" => Tuple,
+            "__tupleLen" => TupleLen,
+            "__tupleGet" => TupleGet,
+            "__tupleSlice" => TupleSlice,
+
+            "__sliceLen" => SliceLen,
+            "__sliceGetP" => SliceGetP,
+            "__sliceSubP" => SliceSubP,
+
+            "__iAdd" => IAdd,
+            "__iSub" => ISub,
+            "__iMul" => IMul,
+            "__iDiv" => IDiv,
+            "__iAddS" => IAddS,
+            "__iSubS" => ISubS,
+            "__iMulS" => IMulS,
+            "__iDivRem" => IDivRem,
+
+            "__iLt" => ILt,
+            "__iLe" => ILe,
+            "__iGt" => IGt,
+            "__iGe" => IGe,
+
+            "__iAnd" => IAnd,
+            "__iOr" => IOr,
+            "__iXor" => IXor,
+            "__iNot" => INot,
+            "__iShl" => IShl,
+            "__iShr" => IShr,
+            "__iShrL" => IShrL,
+            "__iPopCount" => IPopCount,
+
+            "__fAdd" => FAdd,
+            "__fSub" => FSub,
+            "__fMul" => FMul,
+            "__fDiv" => FDiv,
+            "__fEq" => FEq,
+            "__fLt" => FLt,
+            "__fLe" => FLe,
+            "__fGt" => FGt,
+            "__fGe" => FGe,
+            "__iToF" => IToF,
+            "__fToI" => FToI,
+
+            "__symbolFresh" => SymbolFresh,
+
+            "__stringLen" => StringLen,
+            "__stringGet" => StringGet,
+            "__stringSub" => StringSub,
+            "__stringConcat" => StringConcat,
+            "__stringCmp" => StringCmp,
+            "__stringChars" => StringChars,
+            "__stringToSymbol" => StringToSymbol,
+            "__symbolToString" => SymbolToString,
+            "__stringToInt" => StringToInt,
+            "__stringToFloat" => StringToFloat,
+            "__charToInt" => CharToInt,
+            "__intToChar" => IntToChar,
+            "__rope" => Rope,
+            "__toString" => ToString,
+
+            "__bytes" => Bytes,
+            "__bytesLen" => BytesLen,
+            "__bytesGet" => BytesGet,
+            "__bytesSet" => BytesSet,
+            "__bytesSub" => BytesSub,
+            "__utf8Encode" => Utf8Encode,
+            "__utf8Decode" => Utf8Decode,
+            "__byteBuffer" => ByteBuffer,
+            "__bufferLen" => BufferLen,
+            "__bufferPush" => BufferPush,
+            "__bufferAppend" => BufferAppend,
+            "__bufferBytes" => BufferBytes,
+
+            "__promise" => Promise,
+            "__redirect" => Redirect,
+
+            "__eq" => Eq,
+            "__equal" => Equal,
+            "__hash" => Hash,
+            "__type" => Type,
+            "__typeName" => TypeName,
+            "__builtinType" => BuiltinType,
+
+            "__newRecordType" => NewRecordType,
+            "__beginRecord" => BeginRecord,
+            "__recordInit" => RecordInit,
+            "__endRecord" => EndRecord,
+            "__recordSlice" => RecordSlice,
+
+            "__map" => Map,
+            "__mapLen" => MapLen,
+            "__mapGet" => MapGet,
+            "__mapHas" => MapHas,
+            "__mapInsert" => MapInsert,
+            "__mapRemove" => MapRemove,
+            "__mapFold" => MapFold,
+            "__set" => Set,
+            "__setLen" => SetLen,
+            "__setHas" => SetHas,
+            "__setInsert" => SetInsert,
+            "__setRemove" => SetRemove,
+            "__setFold" => SetFold,
+
+            "__denvEmpty" => DenvEmpty,
+            "__denv" => Denv,
+            "__denvGet" => DenvGet,
+
+            "__prompt" => Prompt,
+            "__abort" => Abort,
+            "__abortOnce" => AbortOnce,
+
+            "__raise" => Raise,
+            "__try" => Try,
+
+            "__require" => Require,
+            "__export" => Export,
+            "__member" => Member,
+
+            "__collect" => Collect,
+
+            "__assertP" => AssertP,
+
+            _ => return Err(UnknownPrimOp)
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Function(Pos, Vec<DefRef>, Box<Expr>),
@@ -120,10 +363,12 @@ impl TryFrom<Expr> for Pattern {
 
     fn try_from(expr: Expr) -> Result<Pattern, IllegalPattern> {
         Ok(match expr {
-            Expr::Call(pos, callee, args) => // FIXME: Deal with `apply`:s from CstFactory
-                Pattern::Call(pos, *callee, args.into_iter()
-                                                .map(TryFrom::try_from)
-                                                .collect::<Result<Vec<_>, _>>()?),
+            Expr::Call(pos, callee, args) => {
+                let (callee, args) = undesugar_call(*callee, args);
+                Pattern::Call(pos, callee, args.into_iter()
+                                               .map(TryFrom::try_from)
+                                               .collect::<Result<Vec<_>, _>>()?)
+            },
             Expr::PrimCall(pos, op, args) =>
                 Pattern::PrimCall(pos, op, args.into_iter()
                                                .map(TryFrom::try_from)
@@ -136,6 +381,28 @@ impl TryFrom<Expr> for Pattern {
     }
 }
 
+/// Undo the `apply apply 0 (f, (args...))` desugaring of `CstFactory::call`, producing `f` and
+/// `args`. Other calls (including explicit ones of `apply`) are returned as is.
+fn undesugar_call(callee: Expr, mut args: Vec<Expr>) -> (Expr, Vec<Expr>) {
+    let is_desugared = match (&callee, args.get(2)) {
+        (&Expr::Lex(_, ref def), Some(&Expr::PrimCall(_, PrimOp::Tuple, ref parts))) =>
+            def.borrow().name == "apply" && args.len() == 3 && parts.len() == 2 && match parts[1] {
+                Expr::PrimCall(_, PrimOp::Tuple, _) => true,
+                _ => false
+            },
+        _ => false
+    };
+
+    if is_desugared {
+        if let Some(Expr::PrimCall(_, _, mut parts)) = args.pop() {
+            if let Some(Expr::PrimCall(_, _, fargs)) = parts.pop() {
+                return (parts.pop().unwrap(), fargs);
+            }
+        }
+    }
+    (callee, args)
+}
+
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Def(Pattern, Expr),
@@ -156,6 +423,8 @@ pub struct Case {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Const {
     Int(isize),
+    /// An integer literal that does not fit in an `isize` (as its decimal digits).
+    BigInt(String),
     Float(f64),
     Char(char),
     Bool(bool),
@@ -173,7 +442,8 @@ impl Display for Const {
 
         match self {
             &Int(n) => n.fmt(f),
-            &Float(n) => n.fmt(f),
+            &BigInt(ref digits) => digits.fmt(f),
+            &Float(n) => write!(f, "{:?}", n), // Debug always has a '.' or exponent
             &Char(c) => write!(f, "'{}'", c),
             &Bool(true) => "__true".fmt(f),
             &Bool(false) => "__false".fmt(f),
@@ -229,6 +499,15 @@ impl Default for Pos {
     }
 }
 
+impl Display for Pos {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 pub trait Positioned {
     fn pos(&self) -> &Pos;
 }
@@ -436,6 +715,29 @@ impl CstFactory {
         Expr::Block(self.pos.clone(), stmts, Box::new(expr))
     }
 
+    /// Create a piecewise function from `methods`.
+    ///
+    /// The function takes the closure itself, a method index and the argument tuple. If none of
+    /// `methods` match, the closure is re-invoked with the next method index.
+    pub fn function(&self, methods: Vec<Case>) -> Expr {
+        let closure = Def::new("self");
+        let method_i = Def::new("m");
+        let args = Def::new("args");
+        Expr::Function(self.pos.clone(), vec![closure.clone(), method_i.clone(), args.clone()],
+            Box::new(Expr::Match(
+                self.pos.clone(),
+                Box::new(self.lex_use(&args)),
+                methods,
+                Box::new(self.call(self.lex_use(&closure),
+                                   vec![self.lex_use(&closure),
+                                        self.primcall(PrimOp::IAdd,
+                                                      vec![self.lex_use(&method_i),
+                                                           self.constant(Const::Int(1))]),
+                                        self.lex_use(&args)]))
+            ))
+        )
+    }
+
     pub fn call(&self, callee: Expr, args: Vec<Expr>) -> Expr {
         Expr::Call(self.pos.clone(), Box::new(callee), args)
     }
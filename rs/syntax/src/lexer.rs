@@ -1,8 +1,8 @@
 use std::convert::TryFrom;
 use std::fmt::{self, Display};
+use std::rc::Rc;
 
-use combine::{self, StreamOnce, Positioned, Parser};
-use combine::char::spaces;
+use combine::{self, StreamOnce, Positioned};
 use combine::error::StringStreamError;
 use combine::stream::state::{State, Positioner};
 use combine::stream::{Resetable, StreamErrorFor};
@@ -14,14 +14,16 @@ use cst::{Pos, Const};
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     LParen, RParen, LBracket, RBracket, LBrace, RBrace,
-    Comma, Semicolon,
+    Comma, Semicolon, Dot,
     Eq,
     DArrow,
+    Arrow,
     Bar,
+    Require, Export,
 
     Lex(String),
     Dyn(String),
-    // TODO: Op(String, Precedence),
+    Op(String), // TODO: Precedence
     Const(Const)
 }
 
@@ -33,6 +35,10 @@ impl Token {
     pub fn dyn_name(self) -> Option<String> {
         if let Token::Dyn(name) = self { Some(name) } else { None }
     }
+
+    pub fn op_name(self) -> Option<String> {
+        if let Token::Op(name) = self { Some(name) } else { None }
+    }
 }
 
 impl Display for Token {
@@ -49,14 +55,18 @@ impl Display for Token {
 
             &Comma => write!(f, ","),
             &Semicolon => write!(f, ";"),
+            &Dot => write!(f, "."),
 
             &Eq => write!(f, "="),
             &DArrow => write!(f, "=>"),
+            &Arrow => write!(f, "->"),
             &Bar => write!(f, "|"),
+            &Require => write!(f, "@require"),
+            &Export => write!(f, "@export"),
 
             &Lex(ref name) => name.fmt(f),
             &Dyn(ref name) => write!(f, "${}", name),
-            // &Op(ref var, _) => var.fmt(f),
+            &Op(ref name) => name.fmt(f),
             &Const(ref c) => c.fmt(f)
         }
     }
@@ -115,15 +125,53 @@ pub struct Lexer<'input> {
 
 impl<'input> Lexer<'input> {
     /// Create a new lexer for lexing the given input string.
-    pub fn new(input: &'input str) -> Self {
-        Lexer {
-            chars: {
-                let mut chars = State::with_positioner(input, Pos::default());
-                spaces().parse_stream(&mut chars).unwrap();
-                chars
-            },
+    pub fn new(input: &'input str) -> Self { Lexer::with_file(input, "") }
+
+    /// Create a new lexer for lexing `input`, the contents of `file`.
+    pub fn with_file(input: &'input str, file: &str) -> Self {
+        let pos = Pos { file: Rc::new(file.to_string()), ..Pos::default() };
+        let mut lexer = Lexer {
+            chars: State::with_positioner(input, pos),
             buffer: Vec::new(),
             token_index: 0
+        };
+        lexer.skip_whitespace();
+        lexer
+    }
+
+    /// Skip whitespace and comments (from `#` to the end of the line).
+    fn skip_whitespace(&mut self) {
+        let mut in_comment = false;
+        loop {
+            let checkpoint = self.chars.checkpoint();
+            match self.chars.uncons() {
+                Ok('\n') => in_comment = false,
+                Ok('#') => in_comment = true,
+                Ok(c) if in_comment || c.is_whitespace() => {},
+                _ => {
+                    self.chars.reset(checkpoint);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Push the decimal digits at the start of `self.chars` into `cs`, returning how many there
+    /// were.
+    fn digits(&mut self, cs: &mut String) -> usize {
+        let mut count = 0;
+        loop {
+            let checkpoint = self.chars.checkpoint();
+            match self.chars.uncons() {
+                Ok(c) if c.is_digit(10) => {
+                    cs.push(c);
+                    count += 1;
+                },
+                _ => {
+                    self.chars.reset(checkpoint);
+                    return count;
+                }
+            }
         }
     }
 
@@ -139,31 +187,90 @@ impl<'input> Lexer<'input> {
             '}' => Ok(Token::RBrace),
             ',' => Ok(Token::Comma),
             ';' => Ok(Token::Semicolon),
-            '=' => {
-                let checkpoint = self.chars.checkpoint();
-                match self.chars.uncons() {
-                    Ok('>') => Ok(Token::DArrow),
-                    _ => {
-                        self.chars.reset(checkpoint);
-                        Ok(Token::Eq)
+            '.' => Ok(Token::Dot),
+            c if is_op_char(c) => {
+                let mut cs = String::new();
+                cs.push(c);
+                loop {
+                    let checkpoint = self.chars.checkpoint();
+                    match self.chars.uncons() {
+                        Ok(c) if is_op_char(c) => cs.push(c),
+                        _ => {
+                            self.chars.reset(checkpoint);
+                            break;
+                        }
                     }
                 }
+                Ok(match cs.as_str() {
+                    "=" => Token::Eq,
+                    "=>" => Token::DArrow,
+                    "->" => Token::Arrow,
+                    "|" => Token::Bar,
+                    _ => Token::Op(cs)
+                })
             },
-            '|' => Ok(Token::Bar),
-            c if c.is_digit(10) => {
+            '@' => {
                 let mut cs = String::new();
-                cs.push(c);
                 loop {
                     let checkpoint = self.chars.checkpoint();
                     match self.chars.uncons() {
-                        Ok(c) if c.is_digit(10) => cs.push(c),
+                        Ok(c) if c.is_alphabetic() => cs.push(c),
                         _ => {
                             self.chars.reset(checkpoint);
                             break;
                         }
                     }
                 }
-                Ok(Token::Const(Const::Int(cs.parse().unwrap())))
+                match cs.as_str() {
+                    "require" => Ok(Token::Require),
+                    "export" => Ok(Token::Export),
+                    _ => Err(StringStreamError::UnexpectedParse)
+                }
+            },
+            c if c.is_digit(10) => {
+                let mut cs = String::new();
+                cs.push(c);
+                self.digits(&mut cs);
+
+                let mut is_float = false;
+                let checkpoint = self.chars.checkpoint();
+                match self.chars.uncons() {
+                    Ok('.') => {
+                        let mut fraction = String::from(".");
+                        if self.digits(&mut fraction) > 0 {
+                            cs.push_str(&fraction);
+                            is_float = true;
+                        } else {
+                            self.chars.reset(checkpoint);
+                        }
+                    },
+                    _ => self.chars.reset(checkpoint)
+                }
+                let checkpoint = self.chars.checkpoint();
+                match self.chars.uncons() {
+                    Ok(e) if e == 'e' || e == 'E' => {
+                        let mut exponent = String::new();
+                        let sign_checkpoint = self.chars.checkpoint();
+                        match self.chars.uncons() {
+                            Ok(sign) if sign == '+' || sign == '-' => exponent.push(sign),
+                            _ => self.chars.reset(sign_checkpoint)
+                        }
+                        if self.digits(&mut exponent) > 0 {
+                            cs.push('e');
+                            cs.push_str(&exponent);
+                            is_float = true;
+                        } else {
+                            self.chars.reset(checkpoint);
+                        }
+                    },
+                    _ => self.chars.reset(checkpoint)
+                }
+
+                Ok(Token::Const(if is_float {
+                    Const::Float(cs.parse().unwrap())
+                } else {
+                    cs.parse().map(Const::Int).unwrap_or(Const::BigInt(cs))
+                }))
             },
             '"' => {
                 let mut cs = String::new();
@@ -180,7 +287,9 @@ impl<'input> Lexer<'input> {
                 loop {
                     let checkpoint = self.chars.checkpoint();
                     match self.chars.uncons() {
-                        Ok(c) if c.is_alphanumeric() => cs.push(c),
+                        // Dynamic variables are not resolved lexically, so '.' in their names is
+                        // just a namespacing convention (as in `$Std.Process.arguments`):
+                        Ok(c) if c.is_alphanumeric() || c == '_' || c == '.' => cs.push(c),
                         _ => {
                             self.chars.reset(checkpoint);
                             break;
@@ -189,13 +298,13 @@ impl<'input> Lexer<'input> {
                 }
                 Ok(Token::Dyn(cs))
             },
-            c if c.is_alphabetic() => {
+            c if c.is_alphabetic() || c == '_' => {
                 let mut cs = String::new();
                 cs.push(c);
                 loop {
                     let checkpoint = self.chars.checkpoint();
                     match self.chars.uncons() {
-                        Ok(c) if c.is_alphanumeric() => cs.push(c),
+                        Ok(c) if c.is_alphanumeric() || c == '_' => cs.push(c),
                         _ => {
                             self.chars.reset(checkpoint);
                             break;
@@ -204,24 +313,33 @@ impl<'input> Lexer<'input> {
                 }
                 Ok(Token::Lex(cs))
             },
-            _ => Err(unimplemented!())
+            _ => Err(StringStreamError::UnexpectedParse)
         });
 
-        loop {
-            let checkpoint = self.chars.checkpoint();
-            match self.chars.uncons() {
-                Ok(c) if c.is_whitespace() => {},
-                _ => {
-                    self.chars.reset(checkpoint);
-                    break;
-                }
-            }
-        }
+        self.skip_whitespace();
 
         res
     }
 }
 
+/// Can `c` be part of an operator (or of `=`, `=>`, `->` and `|`)?
+fn is_op_char(c: char) -> bool { "!%&*+-/:<=>?^|~".contains(c) }
+
+/// The number of `(`, `[` and `{` in `input` that are left unclosed (negative if there are more
+/// closing brackets than opening ones). Lexing stops at the first invalid token.
+pub fn open_brackets(input: &str) -> isize {
+    let mut lexer = Lexer::new(input);
+    let mut depth = 0;
+    while let Ok(token) = lexer.uncons() {
+        match token {
+            Token::LParen | Token::LBracket | Token::LBrace => depth += 1,
+            Token::RParen | Token::RBracket | Token::RBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
 impl<'input> Iterator for Lexer<'input> {
     type Item = Result<Token, StreamErrorFor<Self>>;
 
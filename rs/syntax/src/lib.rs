@@ -6,3 +6,5 @@ extern crate pretty;
 pub mod cst;
 mod lexer;
 mod parser;
+
+pub use lexer::open_brackets;
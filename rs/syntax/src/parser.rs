@@ -4,7 +4,7 @@ use combine::stream::{StreamOnce, Resetable};
 use combine::error::StringStreamError;
 
 use lexer::{Token, Lexer};
-use cst::{Expr, Stmt, Pattern, Case, PrimOp, Def, Const,
+use cst::{self, Expr, Stmt, Pattern, Case, PrimOp, Def, Const,
           IllegalPattern, IdFactory, Pos, Positioned};
 
 // ================================================================================================
@@ -22,25 +22,15 @@ impl CstFactory {
     fn pos(&self) -> Pos { self.pos.clone() }
 
     fn function(&self, methods: Vec<Case>) -> Expr {
-        let closure = Def::new("self");
-        let method_i = Def::new("m");
-        let args = Def::new("args");
-        Expr::Function(self.pos(), vec![closure.clone(), method_i.clone(), args.clone()],
-            Box::new(Expr::Match(
-                self.pos(),
-                Box::new(Expr::Lex(self.pos(), args.clone())),
-                methods,
-                Box::new(Expr::Call(self.pos(),
-                                    Box::new(Expr::Lex(self.pos(), closure.clone())),
-                                    vec![Expr::Lex(self.pos(), closure.clone()),
-                                         Expr::PrimCall(self.pos(), PrimOp::IAdd,
-                                                        vec![Expr::Lex(self.pos(), method_i),
-                                                             Expr::Const(self.pos(),
-                                                                         Const::Int(1))]),
-                                         Expr::Lex(self.pos(), args)])
-                )
-            ))
-        )
+        cst::CstFactory::new(self.pos()).function(methods)
+    }
+
+    fn thunk(&self, body: Expr) -> Expr {
+        self.function(vec![Case {
+            pattern: Pattern::PrimCall(self.pos(), PrimOp::Tuple, vec![]),
+            guard: Expr::Const(self.pos(), Const::Bool(true)),
+            body
+        }])
     }
 
     fn call(&self, callee: Expr, args: Vec<Expr>) -> Expr {
@@ -51,6 +41,23 @@ impl CstFactory {
                         Expr::PrimCall(self.pos(), PrimOp::Tuple, vec![callee, arg_tup])];
         Expr::Call(self.pos(), Box::new(apply), args)
     }
+
+    /// Get the module `name` (e.g. `"Std.File"`).
+    fn require(&self, name: String) -> Expr {
+        Expr::PrimCall(self.pos(), PrimOp::Require,
+                       vec![Expr::Const(self.pos(), Const::String(name))])
+    }
+
+    /// A map with the keys and values in `entries`, which alternate.
+    fn map(&self, entries: Vec<Expr>) -> Expr {
+        Expr::PrimCall(self.pos(), PrimOp::Map, entries)
+    }
+
+    /// Get the member `name` of the export record `module`.
+    fn member(&self, module: Expr, name: String) -> Expr {
+        Expr::PrimCall(self.pos(), PrimOp::Member,
+                       vec![module, Expr::Const(self.pos(), Const::Symbol(name))])
+    }
 }
 
 // ================================================================================================
@@ -63,7 +70,9 @@ pub enum ParseError {
         received: Token
     },
     Expr,
-    Pattern(IllegalPattern)
+    Pattern(IllegalPattern),
+    /// A token after the end of the program.
+    Trailing(Token)
 }
 
 impl From<StringStreamError> for ParseError {
@@ -78,6 +87,9 @@ pub type ParseResult<T> = Result<T, ParseError>;
 
 // ================================================================================================
 
+/// The position of the next token.
+fn position(lexer: &Lexer) -> Pos { ::combine::stream::Positioned::position(lexer) }
+
 fn try_parse<T, F>(lexer: &mut Lexer, f: F) -> ParseResult<T>
     where F: FnOnce(&mut Lexer) -> ParseResult<T>
 {
@@ -143,7 +155,12 @@ fn sep1<T, S, F, G>(lexer: &mut Lexer, f: F, separator: G) -> ParseResult<Vec<T>
 // ================================================================================================
 
 pub fn program(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
-    body(lexer, ids)
+    let program = body(lexer, ids)?;
+    match lexer.uncons() {
+        Ok(token) => Err(ParseError::Trailing(token)),
+        Err(StringStreamError::Eoi) => Ok(program),
+        Err(err) => Err(ParseError::Lex(err))
+    }
 }
 
 fn body(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
@@ -160,20 +177,85 @@ fn body(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
             .or_else(|_| Ok((Vec::new(), expr(lexer, ids)?)))
     }
 
+    let pos = position(lexer);
     body_work(lexer, ids).map(|(mut stmts, e)| {
         stmts.reverse();
-        Expr::Block(Pos::default(), stmts, Box::new(e))
+        Expr::Block(pos, stmts, Box::new(e))
     })
 }
 
 fn stmt(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Stmt> {
-    try_parse(lexer, |lexer| {
-        let pattern = Pattern::try_from(expr(lexer, ids)?)?;
-        token(lexer, Token::Eq)?;
-        let value = expr(lexer, ids)?;
-        Ok(Stmt::Def(pattern, value))
+    try_parse(lexer, |lexer| import(lexer, ids))
+        .or_else(|_| try_parse(lexer, |lexer| {
+            let pattern = Pattern::try_from(expr(lexer, ids)?)?;
+            token(lexer, Token::Eq)?;
+            let value = expr(lexer, ids)?;
+            Ok(Stmt::Def(pattern, value))
+        }))
+        .or_else(|_| expr(lexer, ids).map(Stmt::Expr))
+}
+
+/// An import statement: `@require Std.File` (or `@require "Std.File"`) binds `File` to the module
+/// `Std.File`, `@require Std {SyncIO => IO, File}` binds `IO` and `File` to the members `SyncIO`
+/// and `File` of the module `Std` and `@require Std.Fn.(id)` binds `id` to the member `id` of
+/// `Std.Fn` (likewise for operators, as in `@require Std.Fn.(|>)`).
+///
+/// A module can also be destructured by an ordinary definition like
+/// `(update, map) = @require "ListTable"`, which matches its values in the order of its exports.
+fn import(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Stmt> {
+    let pos = position(lexer);
+    let factory = CstFactory::new(pos.clone());
+    token(lexer, Token::Require)?;
+    let path = module_path(lexer)?;
+    let module = factory.require(path.join("."));
+
+    let selection = if token(lexer, Token::LBrace).is_ok() {
+        let selection = sep1(lexer, |lexer| {
+            let name = var_name(lexer)?;
+            let alias = optional(lexer, |lexer| {
+                token(lexer, Token::DArrow)?;
+                var_name(lexer)
+            })?;
+            Ok((name.clone(), alias.unwrap_or(name)))
+        }, |lexer| token(lexer, Token::Comma))?;
+        token(lexer, Token::RBrace)?;
+        selection
+    } else if let Ok(name) = try_parse(lexer, |lexer| {
+        token(lexer, Token::Dot)?;
+        token(lexer, Token::LParen)?;
+        let name = lex_name(lexer).or_else(|_| op_name(lexer))?;
+        token(lexer, Token::RParen)?;
+        Ok(name)
+    }) {
+        vec![(name.clone(), name)]
+    } else {
+        let name = path.last().unwrap();
+        return Ok(Stmt::Def(Pattern::Lex(pos, ids.borrow_mut().usage(name)), module));
+    };
+
+    let (patterns, members): (Vec<Pattern>, Vec<Expr>) = selection.into_iter()
+        .map(|(name, alias)| (Pattern::Lex(pos.clone(), ids.borrow_mut().usage(&alias)),
+                              factory.member(module.clone(), name)))
+        .unzip();
+    Ok(Stmt::Def(Pattern::PrimCall(pos.clone(), PrimOp::Tuple, patterns),
+                 Expr::PrimCall(pos, PrimOp::Tuple, members)))
+}
+
+/// The name of a module after `@require`: `Std.File` or `"Std.File"`. Returns the components of
+/// the name.
+fn module_path(lexer: &mut Lexer) -> ParseResult<Vec<String>> {
+    try_parse(lexer, |lexer| match lexer.uncons()? {
+        Token::Const(Const::String(name)) => Ok(name.split('.').map(String::from).collect()),
+        Token::Lex(name) => {
+            let mut path = vec![name];
+            path.extend(many(lexer, |lexer| try_parse(lexer, |lexer| {
+                token(lexer, Token::Dot)?;
+                lex_name(lexer)
+            }))?);
+            Ok(path)
+        },
+        _ => Err(ParseError::Expr)
     })
-    .or_else(|_| expr(lexer, ids).map(Stmt::Expr))
 }
 
 fn expr(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
@@ -181,24 +263,57 @@ fn expr(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
 }
 
 fn call(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
-    let callee = simple(lexer, ids)?;
+    try_parse(lexer, |lexer| primcall(lexer, ids))
+        .or_else(|_| {
+            let pos = position(lexer);
+            let callee = simple(lexer, ids)?;
+            let args = many(lexer, |lexer| simple(lexer, ids))?;
+            Ok(if !args.is_empty() {
+                CstFactory::new(pos).call(callee, args)
+            } else {
+                callee
+            })
+        })
+}
+
+/// A primop name like `__iAdd` applied to (possibly zero) arguments.
+fn primcall(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
+    let pos = position(lexer);
+    let op = match lexer.uncons()? {
+        Token::Lex(name) => name.parse::<PrimOp>().map_err(|_| ParseError::Expr)?,
+        _ => return Err(ParseError::Expr)
+    };
     let args = many(lexer, |lexer| simple(lexer, ids))?;
-    Ok(if !args.is_empty() {
-        CstFactory::new(Pos::default()).call(callee, args)
-    } else {
-        callee
-    })
+    Ok(Expr::PrimCall(pos, op, args))
 }
 
+/// An atom followed by any number of member accesses (`.name`).
 fn simple(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
+    let pos = position(lexer);
+    let mut expr = atom(lexer, ids)?;
+    while let Ok(name) = try_parse(lexer, |lexer| {
+        token(lexer, Token::Dot)?;
+        lex_name(lexer)
+    }) {
+        expr = CstFactory::new(pos.clone()).member(expr, name);
+    }
+    Ok(expr)
+}
+
+fn atom(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
+    let pos = position(lexer);
     try_parse(lexer, |lexer| match lexer.uncons()? {
         Token::LBrace =>
             try_parse(lexer, |lexer| {
+                let entries = map_entries(lexer, ids)?;
+                Ok(CstFactory::new(pos.clone()).map(entries))
+            })
+            .or_else(|_| try_parse(lexer, |lexer| {
                 let methods = sep1(lexer, |lexer| method(lexer, ids),
                                           |lexer| token(lexer, Token::Semicolon))?;
                 token(lexer, Token::RBrace)?;
-                Ok(CstFactory::new(Pos::default()).function(methods))
-            })
+                Ok(CstFactory::new(pos.clone()).function(methods))
+            }))
             .or_else(|_| {
                 let res = body(lexer, ids)?;
                 token(lexer, Token::RBrace)?;
@@ -207,21 +322,95 @@ fn simple(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Expr> {
         Token::LBracket => {
             let body = body(lexer, ids)?;
             token(lexer, Token::RBracket)?;
-            Ok(Expr::Function(Pos::default(), /* FIXME: */ vec![], Box::new(body)))
+            Ok(CstFactory::new(pos.clone()).thunk(body))
         }
         Token::LParen => {
+            if let Ok(name) = try_parse(lexer, |lexer| {
+                let name = op_name(lexer)?;
+                token(lexer, Token::RParen)?;
+                Ok(name)
+            }) {
+                return Ok(Expr::Lex(pos.clone(), ids.borrow_mut().usage(&name)));
+            }
+            if token(lexer, Token::RParen).is_ok() {
+                return Ok(Expr::PrimCall(pos.clone(), PrimOp::Tuple, Vec::new()));
+            }
+
             let res = expr(lexer, ids)?;
+            let mut vals = many(lexer, |lexer| try_parse(lexer, |lexer| {
+                token(lexer, Token::Comma)?;
+                expr(lexer, ids)
+            }))?;
+            let trailing_comma = token(lexer, Token::Comma).is_ok();
             token(lexer, Token::RParen)?;
-            Ok(res)
+            Ok(if vals.is_empty() && !trailing_comma {
+                res
+            } else {
+                vals.insert(0, res);
+                Expr::PrimCall(pos.clone(), PrimOp::Tuple, vals)
+            })
+        },
+        Token::Lex(name) => Ok(Expr::Lex(pos.clone(), ids.borrow_mut().usage(&name))),
+        Token::Dyn(name) => Ok(Expr::Dyn(pos.clone(), name)),
+        Token::Const(c) => Ok(Expr::Const(pos.clone(), c)),
+        Token::Require => {
+            let path = module_path(lexer)?;
+            Ok(CstFactory::new(pos.clone()).require(path.join(".")))
+        },
+        Token::Export => {
+            token(lexer, Token::LBrace)?;
+            let names = sep1(lexer, var_name, |lexer| token(lexer, Token::Comma))?;
+            token(lexer, Token::RBrace)?;
+            let args = names.into_iter()
+                            .flat_map(|name| {
+                                let value = Expr::Lex(pos.clone(), ids.borrow_mut().usage(&name));
+                                vec![Expr::Const(pos.clone(), Const::Symbol(name)), value]
+                            })
+                            .collect();
+            Ok(Expr::PrimCall(pos.clone(), PrimOp::Export, args))
         },
-        Token::Lex(name) => Ok(Expr::Lex(Pos::default(), ids.borrow_mut().usage(&name))),
-        Token::Dyn(name) => Ok(Expr::Dyn(Pos::default(), name)),
-        Token::Const(c) => Ok(Expr::Const(Pos::default(), c)),
         _ => Err(ParseError::Expr)
     })
 }
 
+/// The rest of a map literal after the `{`: `k -> v, ...}`, or `->}` for the empty map. Returns
+/// the keys and values alternately.
+fn map_entries(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Vec<Expr>> {
+    let entries = if token(lexer, Token::Arrow).is_ok() {
+        Vec::new()
+    } else {
+        let entries = sep1(lexer, |lexer| {
+            let key = expr(lexer, ids)?;
+            token(lexer, Token::Arrow)?;
+            let value = expr(lexer, ids)?;
+            Ok(vec![key, value])
+        }, |lexer| token(lexer, Token::Comma))?;
+        entries.into_iter().flat_map(|entry| entry).collect()
+    };
+    token(lexer, Token::RBrace)?;
+    Ok(entries)
+}
+
+fn lex_name(lexer: &mut Lexer) -> ParseResult<String> {
+    try_parse(lexer, |lexer| lexer.uncons()?.lex_name().ok_or(ParseError::Expr))
+}
+
+fn op_name(lexer: &mut Lexer) -> ParseResult<String> {
+    try_parse(lexer, |lexer| lexer.uncons()?.op_name().ok_or(ParseError::Expr))
+}
+
+/// A lexical variable name, where operators are parenthesized as in `(|>)`.
+fn var_name(lexer: &mut Lexer) -> ParseResult<String> {
+    lex_name(lexer).or_else(|_| try_parse(lexer, |lexer| {
+        token(lexer, Token::LParen)?;
+        let name = op_name(lexer)?;
+        token(lexer, Token::RParen)?;
+        Ok(name)
+    }))
+}
+
 fn method(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Case> {
+    let pos = position(lexer);
     let patterns = many1(lexer, |lexer| simple(lexer, ids))?.into_iter()
                        .map(Pattern::try_from)
                        .collect::<Result<Vec<_>, _>>()?;
@@ -233,7 +422,7 @@ fn method(lexer: &mut Lexer, ids: &RefCell<IdFactory>) -> ParseResult<Case> {
     let body = expr(lexer, ids)?;
     Ok(Case {
         pattern: Pattern::PrimCall(patterns[0].pos().clone(), PrimOp::Tuple, patterns),
-        guard: guard.unwrap_or_else(|| Expr::Const(Pos::default(), Const::Bool(true))),
+        guard: guard.unwrap_or_else(|| Expr::Const(pos, Const::Bool(true))),
         body
     })
 }